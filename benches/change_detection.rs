@@ -0,0 +1,84 @@
+//! Compares the full frame equality check the capture loop used to do with the sampled `ChangeDetector`.
+//!
+//! Run it with `cargo bench --bench change_detection`. It feeds ten seconds of 4K frames at 60 Hz, mostly the same
+//! frame with a change every fourth frame, and prints the time each check takes per frame and the share of a single
+//! core it needs to keep up with 60 Hz.
+
+#[path = "../src/utils/change.rs"]
+mod change;
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+const SIZE: (u32, u32) = (3840, 2160);
+const RATE: u32 = 60;
+const FRAMES: u32 = RATE * 10;
+
+fn main() {
+    let still = RgbaImage::from_fn(SIZE.0, SIZE.1, |x, y| {
+        Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255])
+    });
+    let mut moved = still.clone();
+    for y in 900..1260 {
+        for x in 1600..2240 {
+            moved.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+        }
+    }
+    let frames = [
+        DynamicImage::ImageRgba8(still),
+        DynamicImage::ImageRgba8(moved),
+    ];
+    let frame = |index: u32| &frames[(index % 8 / 4) as usize];
+
+    let mut last_frame = DynamicImage::new_rgba8(1, 1);
+    let mut processed = 0;
+    let full = time(|index| {
+        let img = frame(index);
+        if *img != last_frame {
+            last_frame = img.clone();
+            processed += 1;
+        }
+    });
+    report("Full frame equality", full, processed);
+
+    let mut detector = change::ChangeDetector::default();
+    let mut processed = 0;
+    let sampled = time(|index| {
+        let img = frame(index);
+        if detector.changed(img, (0, 0, img.width(), img.height())) {
+            processed += 1;
+        }
+    });
+    report("Sampled signature", sampled, processed);
+
+    println!(
+        "Sampled signature is {:.0}x faster",
+        full.as_secs_f64() / sampled.as_secs_f64()
+    );
+}
+
+fn time(mut check: impl FnMut(u32)) -> Duration {
+    let start = Instant::now();
+    for index in 0..FRAMES {
+        check(black_box(index));
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, total: Duration, processed: u32) {
+    let per_frame = total / FRAMES;
+    let budget = Duration::from_secs(1) / RATE;
+    println!(
+        "{}: {:.3} ms per frame, {:.1}% of a core at {} Hz, {} of {} frames processed",
+        name,
+        per_frame.as_secs_f64() * 1000.0,
+        per_frame.as_secs_f64() / budget.as_secs_f64() * 100.0,
+        RATE,
+        processed,
+        FRAMES
+    );
+}
@@ -0,0 +1,186 @@
+//! Local stand-in for the OpenRGB SDK server, used to try the OpenRGB output without any OpenRGB devices.
+//!
+//! Run it with `cargo run --example openrgb_stand_in [port]`, enable the OpenRGB output in Wootili-View and add a
+//! target with the controller "Stand-in Device" to the config. Every led update received is printed.
+//!
+//! The OpenRGB client tests include this file to run the stand-in on a free port.
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const SET_CLIENT_NAME: u32 = 50;
+const UPDATE_ZONE_LEDS: u32 = 1052;
+const SET_CUSTOM_MODE: u32 = 1100;
+
+const CONTROLLER_NAME: &str = "Stand-in Device";
+
+struct Zone {
+    name: &'static str,
+    leds: u32,
+    /// (width, height, led at each position)
+    matrix: Option<(u32, u32, &'static [u32])>,
+}
+
+const ZONES: [Zone; 2] = [
+    Zone {
+        name: "Matrix",
+        leds: 6,
+        matrix: Some((3, 2, &[0, 1, 2, 3, 4, 5])),
+    },
+    Zone {
+        name: "Strip",
+        leds: 10,
+        matrix: None,
+    },
+];
+
+fn main() -> io::Result<()> {
+    let port = std::env::args()
+        .nth(1)
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(6742);
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("OpenRGB stand-in listening on 127.0.0.1:{}", port);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        std::thread::spawn(move || {
+            let result = serve(stream, |update| {
+                let colors: Vec<String> = update
+                    .colors
+                    .iter()
+                    .map(|(r, g, b)| format!("#{:02x}{:02x}{:02x}", r, g, b))
+                    .collect();
+                println!(
+                    "Zone {} of controller {}: {}",
+                    update.zone,
+                    update.controller,
+                    colors.join(" ")
+                );
+            });
+            if let Err(e) = result {
+                println!("Client disconnected: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Led colors received for a zone
+pub struct ZoneUpdate {
+    pub controller: u32,
+    pub zone: u32,
+    pub colors: Vec<(u8, u8, u8)>,
+}
+
+/// Answers the requests of a single client until it disconnects, `on_update` gets every led update
+pub fn serve(mut stream: TcpStream, mut on_update: impl FnMut(ZoneUpdate)) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; 16];
+        stream.read_exact(&mut header)?;
+        if &header[0..4] != b"ORGB" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad magic"));
+        }
+
+        let device = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let packet_id = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let size = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let mut data = vec![0u8; size as usize];
+        stream.read_exact(&mut data)?;
+
+        match packet_id {
+            REQUEST_CONTROLLER_COUNT => reply(&mut stream, 0, packet_id, &1u32.to_le_bytes())?,
+            REQUEST_CONTROLLER_DATA => reply(&mut stream, device, packet_id, &controller_data())?,
+            SET_CLIENT_NAME => println!(
+                "Client name: {}",
+                String::from_utf8_lossy(&data).trim_end_matches('\0')
+            ),
+            SET_CUSTOM_MODE => println!("Controller {} set to custom mode", device),
+            UPDATE_ZONE_LEDS => {
+                let count = u16::from_le_bytes(data[8..10].try_into().unwrap()) as usize;
+                on_update(ZoneUpdate {
+                    controller: device,
+                    zone: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+                    colors: data[10..10 + count * 4]
+                        .chunks(4)
+                        .map(|color| (color[0], color[1], color[2]))
+                        .collect(),
+                });
+            }
+            _ => println!("Ignoring packet {}", packet_id),
+        }
+    }
+}
+
+fn reply(stream: &mut TcpStream, device: u32, packet_id: u32, data: &[u8]) -> io::Result<()> {
+    let mut packet = b"ORGB".to_vec();
+    packet.extend_from_slice(&device.to_le_bytes());
+    packet.extend_from_slice(&packet_id.to_le_bytes());
+    packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    packet.extend_from_slice(data);
+    stream.write_all(&packet)
+}
+
+fn push_string(data: &mut Vec<u8>, text: &str) {
+    data.extend_from_slice(&(text.len() as u16 + 1).to_le_bytes());
+    data.extend_from_slice(text.as_bytes());
+    data.push(0);
+}
+
+/// Controller data in protocol version 0 with a single direct mode
+fn controller_data() -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&0i32.to_le_bytes()); // Device type
+    push_string(&mut data, CONTROLLER_NAME);
+    for text in ["Stand-in for testing", "1.0", "0000", "127.0.0.1"] {
+        push_string(&mut data, text);
+    }
+
+    data.extend_from_slice(&1u16.to_le_bytes()); // Mode count
+    data.extend_from_slice(&0i32.to_le_bytes()); // Active mode
+    push_string(&mut data, "Direct");
+    for value in [0u32, 1 << 5, 0, 0, 0, 0, 0, 0, 1] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(&0u16.to_le_bytes()); // Mode colors
+
+    let led_count: u32 = ZONES.iter().map(|zone| zone.leds).sum();
+    data.extend_from_slice(&(ZONES.len() as u16).to_le_bytes());
+    for zone in ZONES {
+        push_string(&mut data, zone.name);
+        data.extend_from_slice(&0i32.to_le_bytes()); // Zone type
+        for value in [zone.leds, zone.leds, zone.leds] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        match zone.matrix {
+            Some((width, height, map)) => {
+                data.extend_from_slice(&(8 + map.len() as u16 * 4).to_le_bytes());
+                data.extend_from_slice(&height.to_le_bytes());
+                data.extend_from_slice(&width.to_le_bytes());
+                for led in map {
+                    data.extend_from_slice(&led.to_le_bytes());
+                }
+            }
+            None => data.extend_from_slice(&0u16.to_le_bytes()),
+        }
+    }
+
+    data.extend_from_slice(&(led_count as u16).to_le_bytes());
+    for led in 0..led_count {
+        push_string(&mut data, &format!("Led {}", led));
+        data.extend_from_slice(&led.to_le_bytes());
+    }
+    data.extend_from_slice(&(led_count as u16).to_le_bytes());
+    data.extend(std::iter::repeat_n(0u8, led_count as usize * 4));
+
+    // The size includes itself
+    let mut packet = ((data.len() + 4) as u32).to_le_bytes().to_vec();
+    packet.append(&mut data);
+    packet
+}
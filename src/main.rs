@@ -7,17 +7,28 @@ use egui_notify::Toasts;
 use image::imageops::FilterType;
 use scorched::{logf, LogData, LogImportance};
 use std::{
-    sync::atomic::{AtomicBool, Ordering},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+    },
     time::Duration,
 };
 use utils::{
+    ambilight::{EdgeSampling, InnerKeys},
     capture::*,
     config::*,
+    device::{RgbDevice, VirtualDevice},
+    downscale::DownscaleMethod,
+    hotplug::{self, DeviceEvent},
+    layout,
+    pipeline::{self, StageKind},
     plugins::{get_available_plugins, update_check_ui, Plugin},
+    recording::{self, RecordingDevice, RECORDING},
+    source::{CaptureError, SourceKind},
     ui::*,
-    wooting,
+    window, wooting,
 };
-use xcap::Monitor;
 
 pub static CLOSE_APP: AtomicBool = AtomicBool::new(false);
 
@@ -30,14 +41,71 @@ fn main() -> Result<(), eframe::Error> {
         gen_config();
     }
 
+    utils::definitions::load_definitions();
     utils::wooting::update_rgb();
+    utils::wooting::refresh_device_info();
 
     CAPTURE_LOCK.store(true, Ordering::Relaxed);
 
-    // Screen thread, captures the screen and sends it to the device
-    std::thread::spawn(|| {
-        capture();
-    });
+    // Runs the capture loop against an in-memory keyboard instead of the Wooting SDK
+    let virtual_device = std::env::args()
+        .any(|arg| arg == "--virtual-device")
+        .then(|| VirtualDevice::new("Virtual Keyboard", (17, 6)));
+    let virtual_frames = virtual_device.as_ref().map(|device| device.frames());
+
+    let devices: Vec<Box<dyn RgbDevice>> = match virtual_device {
+        Some(device) => vec![Box::new(device)],
+        None => {
+            hotplug::start_watcher();
+
+            wooting::get_devices()
+                .into_iter()
+                .map(|device| Box::new(device) as Box<dyn RgbDevice>)
+                .collect()
+        }
+    };
+
+    // Captures every device from the given source instead of the screen, for example a test pattern in demo mode
+    let source_override = std::env::args()
+        .skip_while(|arg| arg != "--source")
+        .nth(1)
+        .and_then(|spec| match SourceKind::parse(&spec) {
+            Some(source) => Some(source),
+            None => {
+                logf!(
+                    Error,
+                    "Unknown capture source {}, capturing the screen",
+                    spec
+                );
+                None
+            }
+        });
+
+    // Plays a recording onto the first device instead of capturing the screen
+    let replay_path = std::env::args()
+        .skip_while(|arg| arg != "--replay")
+        .nth(1)
+        .map(PathBuf::from);
+
+    match replay_path {
+        Some(path) => {
+            let device = devices.into_iter().next().unwrap();
+            std::thread::spawn(move || {
+                recording::replay(&path, device);
+            });
+        }
+        None => {
+            let devices = devices
+                .into_iter()
+                .map(|device| Box::new(RecordingDevice::new(device)) as Box<dyn RgbDevice>)
+                .collect();
+
+            // Screen thread, captures the screen and sends it to the devices
+            std::thread::spawn(move || {
+                capture(devices, source_override);
+            });
+        }
+    }
 
     while CLOSE_APP.load(Ordering::Relaxed) == false {
         eframe::run_native(
@@ -50,28 +118,41 @@ fn main() -> Result<(), eframe::Error> {
         )?;
     }
 
+    if let Some(frames) = virtual_frames {
+        logf!(
+            Info,
+            "Virtual device kept the last {} frames",
+            frames.lock().unwrap().len()
+        );
+    }
+
     Ok(())
 }
 
+struct ConnectedDevice {
+    info: Option<wooting::DeviceInfo>,
+    settings: DeviceSettings,
+    /// Last capture status shown for the device, None while capturing works
+    capture_status: Option<CaptureError>,
+}
+
 struct MyApp {
     toasts: Toasts,
     is_startup: bool,
     plugins: Vec<Plugin>,
-    device_name: String,
-    brightness: u8,
-    reduce_bright_effects: bool,
-    screen: usize,
+    devices: Vec<ConnectedDevice>,
+    selected_device: usize,
     display_rgb_preview: bool,
-    downscale_method: FilterType,
     frame_limit: (u8, u8),
-    red_shift_fix: bool,
-    highlight_wasd: bool,
+    interpolation_rate: Option<u8>,
     dark_mode: bool,
     check_updates: bool,
-    device_creation: String,
-    device_version: String,
-    rgb_size: (u32, u32),
     next_frame: Duration,
+    device_events: Receiver<DeviceEvent>,
+    openrgb: OpenRgbConfig,
+    calibration_wizard: Option<CalibrationWizard>,
+    crop_preview: Option<CropPreview>,
+    screens: ScreenList,
 }
 
 impl Default for MyApp {
@@ -80,48 +161,232 @@ impl Default for MyApp {
             toasts: Toasts::default(),
             is_startup: true,
             plugins: get_available_plugins(),
-            device_name: wooting::get_device_name(),
-            brightness: 100,
-            reduce_bright_effects: false,
-            screen: 0,
+            devices: Vec::new(),
+            selected_device: 0,
             display_rgb_preview: true,
-            downscale_method: FilterType::Triangle,
             frame_limit: (60, 15), // (UI, Capture)
-            red_shift_fix: false,
-            highlight_wasd: false,
+            interpolation_rate: None,
             dark_mode: true,
             check_updates: true,
-            device_creation: wooting::get_device_creation(0),
-            device_version: wooting::get_device_version(),
-            rgb_size: wooting::get_rgb_size().unwrap(),
             next_frame: Duration::from_secs(0),
+            device_events: hotplug::subscribe(),
+            openrgb: OpenRgbConfig::default(),
+            calibration_wizard: None,
+            crop_preview: None,
+            screens: ScreenList::default(),
         }
     }
 }
 
+impl MyApp {
+    fn capture_settings(&self) -> CaptureSettings {
+        CaptureSettings {
+            capture_frame_limit: self.frame_limit.1.into(),
+            interpolation_rate: self.interpolation_rate.map(u32::from),
+            display_rgb_preview: self.display_rgb_preview,
+            devices: self
+                .devices
+                .iter()
+                .map(|device| device.settings.clone())
+                .collect(),
+            openrgb: self.openrgb.clone(),
+        }
+    }
+
+    /// Moves the devices to their new index after one was plugged in or removed. Devices that stayed plugged in keep
+    /// their settings, only new devices get theirs from the config.
+    fn sync_devices(&mut self) {
+        let Some(mut config) = read_config() else {
+            // A broken config is reset the same way as on startup
+            self.is_startup = true;
+            return;
+        };
+
+        let device_info = wooting::device_info();
+        let slots: Vec<(String, Option<wooting::DeviceInfo>)> = match device_info.is_empty() {
+            true => vec![("N/A".to_string(), None)],
+            false => device_info
+                .into_iter()
+                .map(|info| (info.model.clone(), Some(info)))
+                .collect(),
+        };
+
+        // Configs are stored in the same order as the devices, followed by the ones of devices that are not plugged in
+        let mut saved = std::mem::take(&mut config.devices);
+        let known = self.devices.len().min(saved.len());
+        let mut previous: Vec<Option<(ConnectedDevice, DeviceConfig)>> =
+            std::mem::take(&mut self.devices)
+                .into_iter()
+                .zip(saved.drain(..known))
+                .map(Some)
+                .collect();
+
+        let mut devices: Vec<Option<(ConnectedDevice, DeviceConfig)>> = slots
+            .iter()
+            .map(|(_, info)| {
+                let serial = info.as_ref().and_then(|info| info.serial.clone())?;
+                let position = previous.iter().position(|entry| {
+                    entry
+                        .as_ref()
+                        .and_then(|(device, _)| device.info.as_ref())
+                        .is_some_and(|old| old.serial.as_ref() == Some(&serial))
+                })?;
+                let (mut device, device_config) = previous[position].take()?;
+                device.info = info.clone();
+                Some((device, device_config))
+            })
+            .collect();
+
+        // Settings of unplugged devices are kept for the next time they are plugged in
+        saved.splice(
+            0..0,
+            previous
+                .into_iter()
+                .flatten()
+                .map(|(_, device_config)| device_config),
+        );
+
+        for (entry, (model, info)) in devices.iter_mut().zip(slots.iter()) {
+            if entry.is_some() {
+                continue;
+            }
+
+            let device_config = take_device_config(&mut saved, model);
+            let device = ConnectedDevice {
+                info: info.clone(),
+                settings: device_settings(
+                    model,
+                    info.as_ref().map(|info| info.rgb_size).unwrap_or((0, 0)),
+                    &device_config,
+                ),
+                capture_status: None,
+            };
+            *entry = Some((device, device_config));
+        }
+
+        for (device, device_config) in devices.into_iter().flatten() {
+            self.devices.push(device);
+            config.devices.push(device_config);
+        }
+        config.devices.append(&mut saved);
+        self.selected_device = self.selected_device.min(self.devices.len() - 1);
+
+        save_config_option(ConfigChange::AllConfigOptions(config), &mut self.toasts);
+        *CAPTURE_SETTINGS.write().unwrap() = self.capture_settings();
+        CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+    }
+}
+
+fn device_settings(
+    model: &str,
+    rgb_size: (u32, u32),
+    device_config: &DeviceConfig,
+) -> DeviceSettings {
+    DeviceSettings {
+        monitor: device_config.monitor.clone(),
+        span_screens: device_config.span_screens,
+        downscale_method: downscale_index_to_method(device_config.downscale_method_index),
+        key_geometry: device_config.key_geometry,
+        crop: device_config.crop,
+        capture_window: device_config.capture_window.clone(),
+        remove_black_bars: device_config.remove_black_bars,
+        edge_sampling: device_config.edge_sampling,
+        color_stages: device_config.color_stages.clone(),
+        calibration: device_config.calibration,
+        brightness: device_config.brightness,
+        device_name: model.to_string(),
+        rgb_size,
+    }
+}
+
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if self.is_startup {
-            if !cfg!(windows) {
-                self.toasts
-                    .error("This application is not supported on your operating system")
-                    .duration(Some(Duration::from_secs(120)));
+        let mut devices_changed = false;
+        while let Ok(event) = self.device_events.try_recv() {
+            match event {
+                DeviceEvent::Connected(info) => {
+                    self.toasts
+                        .success(format!("Connected to {}", info.model))
+                        .duration(Some(Duration::from_secs(3)));
+                }
+                DeviceEvent::Disconnected(info) => {
+                    self.toasts
+                        .warning(format!("Disconnected from {}", info.model))
+                        .duration(Some(Duration::from_secs(5)));
+                }
             }
-            logf!(Info, "Connected to device Name: {}", self.device_name);
-            match self.device_name.as_str() {
-                "N/A" => {
+
+            devices_changed = true;
+        }
+        if devices_changed && !self.is_startup {
+            self.sync_devices();
+        }
+        if devices_changed {
+            // Docks and monitors with a hub often bring keyboards along, so the screens may have changed as well
+            self.screens.refresh();
+        }
+
+        let capture_status = CAPTURE_STATUS.read().unwrap().clone();
+        for (device, status) in self.devices.iter_mut().zip(capture_status) {
+            if device.capture_status == status {
+                continue;
+            }
+
+            match &status {
+                Some(error) => {
                     self.toasts
-                        .error("No Wooting Device Found")
+                        .error(format!(
+                            "Capture for {} paused: {}",
+                            device.settings.device_name, error
+                        ))
                         .duration(Some(Duration::from_secs(5)));
                 }
-                _ => {
+                None => {
                     self.toasts
-                        .success(format!("Connected to {}", self.device_name))
+                        .success(format!(
+                            "Capture for {} resumed",
+                            device.settings.device_name
+                        ))
                         .duration(Some(Duration::from_secs(3)));
                 }
-            };
+            }
+            device.capture_status = status;
+        }
+
+        if self.is_startup {
+            CAPTURE_LOCK.store(true, Ordering::Relaxed);
+            utils::definitions::load_definitions();
+
+            if !cfg!(windows) {
+                self.toasts
+                    .error("This application is not supported on your operating system")
+                    .duration(Some(Duration::from_secs(120)));
+            }
 
-            let config = match read_config() {
+            let device_info = wooting::device_info();
+            let mut models: Vec<String> =
+                device_info.iter().map(|info| info.model.clone()).collect();
+            if models.is_empty() {
+                models.push("N/A".to_string());
+            }
+
+            // Devices plugged in later get their toast from the device event
+            for model in models
+                .iter()
+                .filter(|model| *model != "N/A" && self.devices.is_empty())
+            {
+                logf!(Info, "Connected to device Name: {}", model);
+                self.toasts
+                    .success(format!("Connected to {}", model))
+                    .duration(Some(Duration::from_secs(3)));
+            }
+            if models.iter().all(|model| model == "N/A") {
+                self.toasts
+                    .error("No Wooting Device Found")
+                    .duration(Some(Duration::from_secs(5)));
+            }
+
+            let mut config = match read_config() {
                 Some(config) => config,
                 None => {
                     reset_config();
@@ -134,29 +399,36 @@ impl eframe::App for MyApp {
                 }
             };
 
-            self.brightness = config.brightness;
-            self.reduce_bright_effects = config.reduce_bright_effects;
-            self.screen = config.screen;
+            arrange_device_configs(&mut config, &models);
+
+            self.devices = models
+                .iter()
+                .zip(config.devices.iter())
+                .enumerate()
+                .map(|(index, (model, device_config))| ConnectedDevice {
+                    info: device_info.get(index).cloned(),
+                    settings: device_settings(
+                        model,
+                        device_info
+                            .get(index)
+                            .map(|info| info.rgb_size)
+                            .unwrap_or((0, 0)),
+                        device_config,
+                    ),
+                    capture_status: CAPTURE_STATUS.read().unwrap().get(index).cloned().flatten(),
+                })
+                .collect();
+            self.selected_device = self.selected_device.min(self.devices.len() - 1);
             self.display_rgb_preview = config.display_rgb_preview;
-            self.downscale_method = downscale_index_to_filter(config.downscale_method_index);
             self.frame_limit = config.frame_limit;
-            self.red_shift_fix = config.red_shift_fix;
-            self.highlight_wasd = config.highlight_wasd;
+            self.interpolation_rate = config.interpolation_rate;
             self.dark_mode = config.dark_mode;
             self.check_updates = config.check_updates;
+            self.openrgb = config.openrgb.clone();
 
-            *CAPTURE_SETTINGS.write().unwrap() = CaptureSettings {
-                screen_index: self.screen,
-                downscale_method: self.downscale_method,
-                capture_frame_limit: self.frame_limit.1.into(),
-                reduce_bright_effects: self.reduce_bright_effects,
-                red_shift_fix: self.red_shift_fix,
-                highlight_wasd: self.highlight_wasd,
-                brightness: self.brightness,
-                device_name: self.device_name.clone(),
-                rgb_size: wooting::get_rgb_size().unwrap_or((0, 0)),
-                display_rgb_preview: self.display_rgb_preview,
-            };
+            save_config_option(ConfigChange::AllConfigOptions(config), &mut self.toasts);
+
+            *CAPTURE_SETTINGS.write().unwrap() = self.capture_settings();
 
             CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             CAPTURE_LOCK.store(false, Ordering::Relaxed);
@@ -175,40 +447,138 @@ impl eframe::App for MyApp {
             ui.separator();
 
             ui.heading("Visual");
-            if ui.add(egui::Slider::new(&mut self.brightness, 50..=150).text("Brightness")).on_hover_text("Adjusts the brightness of the lighting").changed() {
-                save_config_option(ConfigChange::Brightness(self.brightness), &mut self.toasts);
-                CAPTURE_SETTINGS.write().unwrap().brightness = self.brightness;
+            if self.devices.len() > 1 {
+                egui::ComboBox::from_label("Device")
+                    .selected_text(format!("{}: {}", self.selected_device + 1, self.devices[self.selected_device].settings.device_name))
+                    .show_ui(ui, |ui| {
+                        for (index, device) in self.devices.iter().enumerate() {
+                            ui.selectable_value(&mut self.selected_device, index, format!("{}: {}", index + 1, device.settings.device_name));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Select the device to change the settings of, each device has its own screen and lighting settings");
+            }
+
+            let index = self.selected_device;
+            let device = &mut self.devices[index].settings;
+            if ui.add(egui::Slider::new(&mut device.brightness, 50..=150).text("Brightness")).on_hover_text("Adjusts the brightness of the lighting").changed() {
+                save_config_option(ConfigChange::Brightness(index, device.brightness), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].brightness = device.brightness;
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
-            if ui.add(egui::Slider::new(&mut self.screen, 0..=Monitor::all().unwrap().len() - 1).text("Screen")).on_hover_text("Select the screen to capture").changed() {
-                save_config_option(ConfigChange::Screen(self.screen), &mut self.toasts);
-                CAPTURE_SETTINGS.write().unwrap().screen_index = self.screen;
+            let mut stages_changed = stage_slider(ui, &mut device.color_stages, |stage| match stage { StageKind::Saturation(value) => Some(value), _ => None }, 0.0..=2.0, "Saturation", "Scales how colorful the lighting is without changing its brightness, 1 leaves it alone", Some(1.0));
+            stages_changed |= stage_slider(ui, &mut device.color_stages, |stage| match stage { StageKind::Vibrance(value) => Some(value), _ => None }, -1.0..=1.0, "Vibrance", "Makes dull, washed out colors more colorful while leaving colors that already stand out mostly alone", Some(0.0));
+            stages_changed |= stage_slider(ui, &mut device.color_stages, |stage| match stage { StageKind::Contrast(value) => Some(value), _ => None }, 0.5..=2.0, "Contrast", "Spreads the lighting further away from middle gray, 1 leaves it alone", Some(1.0));
+            if ui.add_enabled_ui(!device.span_screens, |ui| screen_dropdown(ui, &mut self.screens, &mut device.monitor)).inner {
+                save_config_option(ConfigChange::Screen(index, device.monitor.clone()), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].monitor = device.monitor.clone();
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
-            if ui.checkbox(&mut self.reduce_bright_effects, "Reduce Bright Effects").on_hover_text("Reduces brightness when the screen is very bright").changed() {
-                save_config_option(ConfigChange::ReduceBrightEffects(self.reduce_bright_effects), &mut self.toasts);
-                CAPTURE_SETTINGS.write().unwrap().reduce_bright_effects = self.reduce_bright_effects;
+            if ui.checkbox(&mut device.span_screens, "Span All Screens").on_hover_text("Captures every screen as one desktop at their real positions, so each part of the keyboard follows the screen above it").changed() {
+                save_config_option(ConfigChange::SpanScreens(index, device.span_screens), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].span_screens = device.span_screens;
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
-            if ui.checkbox(&mut self.red_shift_fix, "Red Shift Fix").on_hover_text("Fixes the red shift/hue issue on some Wooting keyboards due to the stock keycaps or from custom switches like the Geon Raptor HE").changed() {
-                save_config_option(ConfigChange::RedShiftFix(self.red_shift_fix), &mut self.toasts);
-                CAPTURE_SETTINGS.write().unwrap().red_shift_fix = self.red_shift_fix;
+            stages_changed |= stage_checkbox(ui, &mut device.color_stages, |stage| matches!(stage, StageKind::Smoothing { .. }), "Smooth Transitions", "Fades each key towards its new color instead of jumping to it, which softens scene cuts and flashes");
+            if pipeline::stage_enabled(&mut device.color_stages, |stage| matches!(stage, StageKind::Smoothing { .. })).is_some_and(|enabled| *enabled) {
+                stages_changed |= stage_slider(ui, &mut device.color_stages, |stage| match stage { StageKind::Smoothing { attack, .. } => Some(attack), _ => None }, 0.0..=1.0, "Attack", "Seconds a key takes to get most of the way to a brighter color", None);
+                stages_changed |= stage_slider(ui, &mut device.color_stages, |stage| match stage { StageKind::Smoothing { decay, .. } => Some(decay), _ => None }, 0.0..=2.0, "Decay", "Seconds a key takes to get most of the way to a darker color", None);
+            }
+            stages_changed |= stage_checkbox(ui, &mut device.color_stages, |stage| matches!(stage, StageKind::FlashLimiter { .. }), "Reduce Bright Effects", "Smoothly dims the lighting while it flashes more than three times a second, like strobes and explosions, to keep it safe for photosensitive viewers");
+            ui.horizontal(|ui| {
+                stages_changed |= stage_checkbox(ui, &mut device.color_stages, |stage| matches!(stage, StageKind::Calibration), "Color Calibration", "Corrects the colors of the keyboard to match the screen, like the red shift of the stock keycaps or of custom switches like the Geon Raptor HE");
+                if ui.button("Calibrate").on_hover_text("Shows reference colors on the screen and the keyboard to adjust the calibration of this device until they match").clicked() {
+                    self.calibration_wizard = Some(CalibrationWizard::new(index, device.calibration));
+                }
+            });
+            stages_changed |= stage_checkbox(ui, &mut device.color_stages, |stage| matches!(stage, StageKind::Overrides(_)), "Highlight WASD", "Highlights the WASD keys to be able to see easily while gaming");
+            let has_layout = layout::get_layout(&device.device_name).is_some();
+            if ui.add_enabled(has_layout, egui::Checkbox::new(&mut device.key_geometry, "Key Geometry")).on_hover_text("Samples the area of the screen under each key using the physical layout of the device, so wide keys like the spacebar average over their whole width").changed() {
+                save_config_option(ConfigChange::KeyGeometry(index, device.key_geometry), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].key_geometry = device.key_geometry;
+                CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+            }
+            let mut capture_window = device.capture_window.is_some();
+            let mut window_changed = ui.checkbox(&mut capture_window, "Capture Window").on_hover_text("Captures a single window instead of the whole screen, the window is followed across screens and the screen is captured while it is closed or minimized").changed();
+            if window_changed {
+                device.capture_window = capture_window.then(String::new);
+            }
+            if let Some(query) = device.capture_window.as_mut() {
+                ui.horizontal(|ui| {
+                    ui.label("Window:");
+                    window_changed |= ui.add(egui::TextEdit::singleline(query).hint_text("Title or app name").desired_width(160.0)).on_hover_text("Part of the title or app name of the window to capture, the first match is used").lost_focus();
+                    ui.menu_button("Pick", |ui| {
+                        for title in window::window_titles() {
+                            if ui.button(&title).clicked() {
+                                *query = title;
+                                window_changed = true;
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+            }
+            if window_changed {
+                save_config_option(ConfigChange::CaptureWindow(index, device.capture_window.clone()), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].capture_window = device.capture_window.clone();
+                CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+            }
+            if ui.checkbox(&mut device.remove_black_bars, "Remove Black Bars").on_hover_text("Finds black bars that stay in place, like the ones around films and ultrawide games, and crops them away so the edges of the keyboard are not left dark").changed() {
+                save_config_option(ConfigChange::RemoveBlackBars(index, device.remove_black_bars), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].remove_black_bars = device.remove_black_bars;
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
-            if ui.checkbox(&mut self.highlight_wasd, "Highlight WASD").on_hover_text("Highlights the WASD keys to be able to see easily while gaming").changed() {
-                save_config_option(ConfigChange::HighlightWASD(self.highlight_wasd), &mut self.toasts);
-                CAPTURE_SETTINGS.write().unwrap().highlight_wasd = self.highlight_wasd;
+            let mut crop_enabled = device.crop.is_some();
+            if ui.checkbox(&mut crop_enabled, "Crop Capture").on_hover_text("Only captures part of the screen, for example just the game viewport or a chat panel").changed() {
+                device.crop = crop_enabled.then_some((0.0, 0.0, 1.0, 1.0));
+                save_config_option(ConfigChange::Crop(index, device.crop), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].crop = device.crop;
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
-            ui.menu_button("Downscale Method", |ui| {
-                downscale_label(ui, &mut self.downscale_method, FilterType::Nearest, "Nearest", "Fast and picks on up on small details but is inconsistent, can completly mask elements on screen", &mut self.toasts);
-                downscale_label(ui, &mut self.downscale_method, FilterType::Triangle, "Triangle", "Overall good results and is fast, best speed to quality ratio (Default)", &mut self.toasts);
-                downscale_label(ui, &mut self.downscale_method, FilterType::Gaussian, "Gaussian", "Fast but gives poor results", &mut self.toasts);
-                downscale_label(ui, &mut self.downscale_method, FilterType::CatmullRom, "CatmullRom", "Good results but is slow, similar results to Lanczos3", &mut self.toasts);
-                downscale_label(ui, &mut self.downscale_method, FilterType::Lanczos3, "Lanczos3", "Gives the best results but is slowest", &mut self.toasts);
+            if let Some(crop) = device.crop.as_mut() {
+                CAPTURE_THUMBNAIL_REQUEST.store(true, Ordering::Relaxed);
+                let thumbnail = CAPTURE_THUMBNAIL.read().unwrap().get(index).cloned().flatten();
+                if crop_editor(ui, thumbnail.as_ref(), &mut self.crop_preview, crop) {
+                    save_config_option(ConfigChange::Crop(index, Some(*crop)), &mut self.toasts);
+                    CAPTURE_SETTINGS.write().unwrap().devices[index].crop = Some(*crop);
+                    CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+                }
+            }
+            let mut edge_sampling = device.edge_sampling.is_some();
+            let mut edge_changed = ui.checkbox(&mut edge_sampling, "TV Ambilight").on_hover_text("Lights only the edge of the keyboard from bands along the edges of the screen, like the bias lighting behind a TV, far less distracting while typing").changed();
+            if edge_changed {
+                device.edge_sampling = edge_sampling.then(EdgeSampling::default);
+            }
+            if let Some(settings) = device.edge_sampling.as_mut() {
+                edge_changed |= ui.add(egui::Slider::new(&mut settings.depth, 0.02..=0.3).text("Edge Depth")).on_hover_text("How far into the screen each edge is sampled, as a part of its width and height").changed();
+                edge_changed |= ui.horizontal(|ui| {
+                    ui.label("Inner Keys:");
+                    [(InnerKeys::Interpolate, "Blend", "Blends the edge colors towards the middle of the keyboard"), (InnerKeys::Average, "Average", "Lights the inner keys with the average color of the edges"), (InnerKeys::Off, "Off", "Leaves the inner keys off")]
+                        .into_iter()
+                        .fold(false, |changed, (inner, label, hover_text)| ui.selectable_value(&mut settings.inner, inner, label).on_hover_text(hover_text).changed() || changed)
+                }).inner;
+            }
+            if edge_changed {
+                save_config_option(ConfigChange::EdgeSampling(index, device.edge_sampling), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].edge_sampling = device.edge_sampling;
+                CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+            }
+            ui.add_enabled_ui(device.edge_sampling.is_none(), |ui| ui.menu_button("Downscale Method", |ui| {
+                downscale_label(ui, index, &mut device.downscale_method, DownscaleMethod::Area, "Area", "Averages the screen under each key straight from the capture, by far the fastest and smooth but can wash out small details", &mut self.toasts);
+                downscale_label(ui, index, &mut device.downscale_method, DownscaleMethod::Filter(FilterType::Nearest), "Nearest", "Fast and picks on up on small details but is inconsistent, can completly mask elements on screen", &mut self.toasts);
+                downscale_label(ui, index, &mut device.downscale_method, DownscaleMethod::Filter(FilterType::Triangle), "Triangle", "Overall good results and is fast, best speed to quality ratio (Default)", &mut self.toasts);
+                downscale_label(ui, index, &mut device.downscale_method, DownscaleMethod::Filter(FilterType::Gaussian), "Gaussian", "Fast but gives poor results", &mut self.toasts);
+                downscale_label(ui, index, &mut device.downscale_method, DownscaleMethod::Filter(FilterType::CatmullRom), "CatmullRom", "Good results but is slow, similar results to Lanczos3", &mut self.toasts);
+                downscale_label(ui, index, &mut device.downscale_method, DownscaleMethod::Filter(FilterType::Lanczos3), "Lanczos3", "Gives the best results but is slowest", &mut self.toasts);
                 ui.separator();
                 ui.label("Note: The downscale methods are sorted in order by quality and performance, the default is triangle.");
-            });
+            }));
+            stages_changed |= ui.collapsing("Color Pipeline", |ui| color_stages_editor(ui, &mut device.color_stages)).body_returned.unwrap_or(false);
+            if stages_changed {
+                save_config_option(ConfigChange::ColorStages(index, device.color_stages.clone()), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().devices[index].color_stages = device.color_stages.clone();
+                CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+            }
             ui.separator();
 
             ui.heading("Performance");
@@ -220,15 +590,47 @@ impl eframe::App for MyApp {
                 CAPTURE_SETTINGS.write().unwrap().capture_frame_limit = self.frame_limit.1.into();
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
+            let mut interpolate = self.interpolation_rate.is_some();
+            let mut interpolation_changed = ui.checkbox(&mut interpolate, "Interpolate Frames").on_hover_text("Draws the lighting more often than the screen is captured, blending from each frame to the next so motion looks smooth at a low RGB FPS Cap").changed();
+            if interpolation_changed {
+                self.interpolation_rate = interpolate.then_some(60);
+            }
+            if let Some(rate) = self.interpolation_rate.as_mut() {
+                interpolation_changed |= ui.add(egui::Slider::new(rate, 30..=120).text("Interpolated FPS")).on_hover_text("How often the blended lighting is drawn, only used while it is above the RGB FPS Cap").changed();
+            }
+            if interpolation_changed {
+                save_config_option(ConfigChange::InterpolationRate(self.interpolation_rate), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().interpolation_rate = self.interpolation_rate.map(u32::from);
+                CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+            }
 
-            let frame_rgb_size = self.rgb_size;
-
-            let allow_preview = frame_rgb_size.0 != 0 && frame_rgb_size.1 != 0;
+            let allow_preview = self
+                .devices
+                .iter()
+                .any(|device| device.settings.rgb_size.0 != 0 && device.settings.rgb_size.1 != 0);
             if ui.add_enabled(allow_preview, egui::Checkbox::new(&mut self.display_rgb_preview, "Display RGB Preview")).on_hover_text("Displays a preview of the lighting, this can be disabled to improve performance").changed() {
                 save_config_option(ConfigChange::DisplayRgbPreview(self.display_rgb_preview), &mut self.toasts);
             }
             ui.separator();
 
+            ui.heading("OpenRGB");
+            let mut openrgb_changed = ui.checkbox(&mut self.openrgb.enabled, "OpenRGB Output").on_hover_text("Sends the lighting to devices controlled by OpenRGB, the OpenRGB SDK server has to be running").changed();
+            ui.add_enabled_ui(self.openrgb.enabled, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Host:");
+                    openrgb_changed |= ui.add(egui::TextEdit::singleline(&mut self.openrgb.host).desired_width(120.0)).on_hover_text("Address of the OpenRGB SDK server").lost_focus();
+                    ui.label("Port:");
+                    openrgb_changed |= ui.add(egui::DragValue::new(&mut self.openrgb.port)).on_hover_text("Port of the OpenRGB SDK server, 6742 by default").changed();
+                });
+                ui.label(format!("Mapped Targets: {}", self.openrgb.targets.len())).on_hover_text("Which device's lighting goes to which OpenRGB device and zone is set in the openrgb targets of the config file, each target is (source: device number starting at 0, controller: \"OpenRGB device name\", zone: Some(\"zone name\") or None for every zone)");
+            });
+            if openrgb_changed {
+                save_config_option(ConfigChange::OpenRgb(self.openrgb.clone()), &mut self.toasts);
+                CAPTURE_SETTINGS.write().unwrap().openrgb = self.openrgb.clone();
+                CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+            }
+            ui.separator();
+
             ui.heading("Application");
             ui.horizontal(|ui| {
                 ui.label("Loaded Plugins:").on_hover_ui(|ui| {
@@ -268,18 +670,23 @@ impl eframe::App for MyApp {
                     .info("Config file has been reset")
                     .duration(Some(Duration::from_secs(1)));
 
-                let new_config = read_config().unwrap();
+                let mut new_config = read_config().unwrap();
+                let models: Vec<String> = self
+                    .devices
+                    .iter()
+                    .map(|device| device.settings.device_name.clone())
+                    .collect();
+                arrange_device_configs(&mut new_config, &models);
 
-                self.brightness = new_config.brightness;
-                self.reduce_bright_effects = new_config.reduce_bright_effects;
-                self.screen = new_config.screen;
+                for (device, device_config) in self.devices.iter_mut().zip(new_config.devices.iter()) {
+                    device.settings = device_settings(&device.settings.device_name, device.settings.rgb_size, device_config);
+                }
                 self.display_rgb_preview = new_config.display_rgb_preview;
-                self.downscale_method = downscale_index_to_filter(new_config.downscale_method_index);
                 self.frame_limit = new_config.frame_limit;
-                self.red_shift_fix = new_config.red_shift_fix;
-                self.highlight_wasd = new_config.highlight_wasd;
+                self.interpolation_rate = new_config.interpolation_rate;
                 self.dark_mode = new_config.dark_mode;
                 self.check_updates = new_config.check_updates;
+                self.openrgb = new_config.openrgb.clone();
 
                 if self.dark_mode {
                     ctx.set_visuals(egui::Visuals::dark());
@@ -292,22 +699,19 @@ impl eframe::App for MyApp {
                     &mut self.toasts,
                 );
 
-                *CAPTURE_SETTINGS.write().unwrap() = CaptureSettings {
-                    screen_index: self.screen,
-                    downscale_method: self.downscale_method,
-                    capture_frame_limit: self.frame_limit.1.into(),
-                    reduce_bright_effects: self.reduce_bright_effects,
-                    red_shift_fix: self.red_shift_fix,
-                    highlight_wasd: self.highlight_wasd,
-                    brightness: self.brightness,
-                    device_name: self.device_name.clone(),
-                    rgb_size: wooting::get_rgb_size().unwrap_or((0, 0)),
-                    display_rgb_preview: self.display_rgb_preview,
-                };
+                *CAPTURE_SETTINGS.write().unwrap() = self.capture_settings();
 
                 CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
             }
 
+            let recording = RECORDING.load(Ordering::Relaxed);
+            if ui.button(if recording { "Stop Recording" } else { "Start Recording" }).on_hover_text("Records the colors sent to each device to a file in the recordings folder, a recording can be played back by starting the app with --replay followed by the file path").clicked() {
+                RECORDING.store(!recording, Ordering::Relaxed);
+                self.toasts
+                    .info(if recording { "Recording stopped" } else { "Recording started" })
+                    .duration(Some(Duration::from_secs(2)));
+            }
+
             clean_logs_button(ui, &mut self.toasts);
 
             // Rewriten plugin rendering
@@ -323,23 +727,71 @@ impl eframe::App for MyApp {
             }
 
             if !self.is_startup {
-                egui::SidePanel::right("lighting_preview_panel").width_range(Rangef::new((self.rgb_size.0 * 15) as f32, (self.rgb_size.0 * 22) as f32)).show(ctx, |ui| {
-                    if self.display_rgb_preview {
-                        match CAPTURE_PREVIEW.read().unwrap().clone() {
-                            Some(preview) => {
-                                rgb_preview(ui, frame_rgb_size, preview.clone());
-                            }
-                            None => {
-                                ui.heading("No Preview Available");
+                let preview_width = self
+                    .devices
+                    .iter()
+                    .map(|device| device.settings.rgb_size.0)
+                    .max()
+                    .unwrap_or(0);
+
+                egui::SidePanel::right("lighting_preview_panel").width_range(Rangef::new((preview_width * 15) as f32, (preview_width * 22) as f32)).show(ctx, |ui| {
+                    device_info_header(ui, &mut self.toasts, &mut self.is_startup);
+
+                    let previews = CAPTURE_PREVIEW.read().unwrap().clone();
+                    let letterboxes = CAPTURE_LETTERBOX.read().unwrap().clone();
+                    for (index, device) in self.devices.iter().enumerate() {
+                        ui.separator();
+
+                        capture_status_label(ui, device.capture_status.as_ref());
+                        // A paused capture would leave the last frame frozen in the preview
+                        if self.display_rgb_preview && device.capture_status.is_none() {
+                            match previews.get(index).cloned().flatten() {
+                                Some(preview) => {
+                                    rgb_preview(ui, device.settings.rgb_size, preview);
+                                    if let Some(letterbox) = letterboxes.get(index).cloned().flatten() {
+                                        letterbox_preview(ui, letterbox);
+                                    }
+                                }
+                                None => {
+                                    ui.heading("No Preview Available");
+                                }
                             }
                         }
-                        //rgb_preview(ui, frame_rgb_size, CAPTURE_PREVIEW.read().unwrap().clone());
+                        display_device_info(ui, device.info.as_ref());
                     }
-                    display_device_info(ui, &mut self.toasts, &mut self.device_name, &mut self.device_creation, &mut self.device_version, &mut self.is_startup, frame_rgb_size);
                 });
             }
         });
 
+        if let Some(wizard) = self.calibration_wizard.as_mut() {
+            let result = calibration_wizard(ctx, wizard);
+            *CALIBRATION_PREVIEW.write().unwrap() = Some(wizard.preview());
+
+            if !matches!(result, WizardResult::Open) {
+                if let (WizardResult::Finished, Some(device)) =
+                    (result, self.devices.get_mut(wizard.device))
+                {
+                    device.settings.calibration = wizard.calibration;
+                    save_config_option(
+                        ConfigChange::Calibration(wizard.device, wizard.calibration),
+                        &mut self.toasts,
+                    );
+                    CAPTURE_SETTINGS.write().unwrap().devices[wizard.device].calibration =
+                        wizard.calibration;
+                    CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
+                    self.toasts
+                        .success(format!(
+                            "Calibration saved for {}",
+                            device.settings.device_name
+                        ))
+                        .duration(Some(Duration::from_secs(3)));
+                }
+
+                *CALIBRATION_PREVIEW.write().unwrap() = None;
+                self.calibration_wizard = None;
+            }
+        }
+
         self.toasts.show(ctx);
 
         self.next_frame =
@@ -349,19 +801,32 @@ impl eframe::App for MyApp {
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let mut changes = vec![
+            ConfigChange::DisplayRgbPreview(self.display_rgb_preview),
+            ConfigChange::FrameLimit(self.frame_limit),
+            ConfigChange::InterpolationRate(self.interpolation_rate),
+            ConfigChange::Darkmode(self.dark_mode),
+            ConfigChange::CheckUpdates(self.check_updates),
+            ConfigChange::OpenRgb(self.openrgb.clone()),
+        ];
+        for (index, device) in self.devices.iter().enumerate() {
+            changes.extend([
+                ConfigChange::Brightness(index, device.settings.brightness),
+                ConfigChange::Screen(index, device.settings.monitor.clone()),
+                ConfigChange::SpanScreens(index, device.settings.span_screens),
+                ConfigChange::DownscaleMethod(index, device.settings.downscale_method),
+                ConfigChange::KeyGeometry(index, device.settings.key_geometry),
+                ConfigChange::Crop(index, device.settings.crop),
+                ConfigChange::CaptureWindow(index, device.settings.capture_window.clone()),
+                ConfigChange::RemoveBlackBars(index, device.settings.remove_black_bars),
+                ConfigChange::EdgeSampling(index, device.settings.edge_sampling),
+                ConfigChange::ColorStages(index, device.settings.color_stages.clone()),
+                ConfigChange::Calibration(index, device.settings.calibration),
+            ]);
+        }
+
         save_config_option(
-            ConfigChange::MultipleConfigOptions(vec![
-                ConfigChange::Brightness(self.brightness),
-                ConfigChange::ReduceBrightEffects(self.reduce_bright_effects),
-                ConfigChange::Screen(self.screen),
-                ConfigChange::DisplayRgbPreview(self.display_rgb_preview),
-                ConfigChange::DownscaleMethod(self.downscale_method),
-                ConfigChange::FrameLimit(self.frame_limit),
-                ConfigChange::RedShiftFix(self.red_shift_fix),
-                ConfigChange::HighlightWASD(self.highlight_wasd),
-                ConfigChange::Darkmode(self.dark_mode),
-                ConfigChange::CheckUpdates(self.check_updates),
-            ]),
+            ConfigChange::MultipleConfigOptions(changes),
             &mut self.toasts,
         );
         wooting::exit_rgb();
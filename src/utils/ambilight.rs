@@ -0,0 +1,323 @@
+use std::borrow::Cow;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    downscale::{average, Region},
+    layout::{Key, KeyLayout},
+};
+
+/// How the keys away from the edge of the keyboard are lit while sampling the edges of the screen
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum InnerKeys {
+    /// Blends the colors of the edge keys towards the middle, the nearest edge counts the most
+    Interpolate,
+    /// The average color of every edge key
+    Average,
+    /// Left off so only the edge of the keyboard is lit
+    Off,
+}
+
+/// Lights the edge of the keyboard from bands along the matching edges of the screen, like the bias lighting of a TV
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct EdgeSampling {
+    /// Depth of the sampled bands as a fraction of the width and height of the screen
+    pub depth: f32,
+    pub inner: InnerKeys,
+}
+
+impl Default for EdgeSampling {
+    fn default() -> Self {
+        Self {
+            depth: 0.1,
+            inner: InnerKeys::Interpolate,
+        }
+    }
+}
+
+/// Samples the edges of `region` of `img` onto the keys along the edge of the board, the other keys are filled in
+/// by `settings.inner`. Pixel (x, y) of the result lights matrix key (y + first_row, x), pixels without a key in
+/// `keys` are left black.
+///
+/// Each key on the top or bottom edge averages the part of the top or bottom band above or below it, the same goes
+/// for the left and right edges, so the corner keys take the corners of the screen.
+pub fn sample_edges(
+    img: &DynamicImage,
+    region: Region,
+    size: (u32, u32),
+    first_row: u8,
+    keys: &KeyLayout,
+    settings: EdgeSampling,
+) -> DynamicImage {
+    let raw = match img.as_rgba8() {
+        Some(raw) => Cow::Borrowed(raw),
+        None => Cow::Owned(img.to_rgba8()),
+    };
+    let (x, y, width, height) = region;
+    let band_width = ((width as f32 * settings.depth) as u32).clamp(1, width);
+    let band_height = ((height as f32 * settings.depth) as u32).clamp(1, height);
+    let (layout_width, layout_height) = keys.size();
+    // Part of the region under a span of the board, at least a pixel wide
+    let span = |start: u32, length: u32, from: f32, to: f32, board: f32| {
+        let begin = start + (from / board * length as f32) as u32;
+        let end = start + (to / board * length as f32) as u32;
+        (
+            begin.min(start + length - 1),
+            end.clamp(begin + 1, start + length),
+        )
+    };
+
+    let pixels: Vec<Option<(u32, u32)>> = keys
+        .keys
+        .iter()
+        .map(|key| {
+            let row = key.row.checked_sub(first_row)? as u32;
+            (row < size.1 && (key.column as u32) < size.0).then_some((key.column as u32, row))
+        })
+        .collect();
+    let edges = board_edges(&keys.keys);
+
+    let mut output = RgbaImage::new(size.0, size.1);
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+    for ((key, edge), pixel) in keys.keys.iter().zip(edges.iter()).zip(pixels.iter()) {
+        let Some((column, row)) = *pixel else {
+            continue;
+        };
+        if !edge.any() {
+            continue;
+        }
+
+        let (x0, x1) = match (edge.left, edge.right) {
+            (true, _) => (x, x + band_width),
+            (false, true) => (x + width - band_width, x + width),
+            _ => span(x, width, key.x, key.x + key.width, layout_width),
+        };
+        let (y0, y1) = match (edge.top, edge.bottom) {
+            (true, _) => (y, y + band_height),
+            (false, true) => (y + height - band_height, y + height),
+            _ => span(y, height, key.y, key.y + key.height, layout_height),
+        };
+
+        let color = average(&raw, (x0, y0, x1, y1));
+        for (total, channel) in sum.iter_mut().zip(color.0) {
+            *total += channel as u32;
+        }
+        count += 1;
+        output.put_pixel(column, row, color);
+    }
+
+    let inner_keys = keys
+        .keys
+        .iter()
+        .zip(edges.iter())
+        .zip(pixels.iter())
+        .filter_map(|((key, edge), pixel)| (!edge.any()).then_some((key, (*pixel)?)));
+    match settings.inner {
+        InnerKeys::Off => {}
+        InnerKeys::Average => {
+            let [r, g, b] = sum.map(|channel| (channel / count.max(1)) as u8);
+            for (_, (column, row)) in inner_keys {
+                output.put_pixel(column, row, Rgba([r, g, b, 255]));
+            }
+        }
+        InnerKeys::Interpolate => {
+            let edge_keys: Vec<(&Key, Edges, Rgba<u8>)> = keys
+                .keys
+                .iter()
+                .zip(edges.iter())
+                .zip(pixels.iter())
+                .filter(|((_, edge), _)| edge.any())
+                .filter_map(|((key, edge), pixel)| {
+                    let (column, row) = (*pixel)?;
+                    Some((key, *edge, *output.get_pixel(column, row)))
+                })
+                .collect();
+
+            for (key, (column, row)) in inner_keys {
+                output.put_pixel(column, row, interpolate(&edge_keys, key));
+            }
+        }
+    }
+
+    DynamicImage::ImageRgba8(output)
+}
+
+/// Which edges of the board a key is on
+#[derive(Clone, Copy)]
+struct Edges {
+    top: bool,
+    bottom: bool,
+    left: bool,
+    right: bool,
+}
+
+impl Edges {
+    fn any(&self) -> bool {
+        self.top || self.bottom || self.left || self.right
+    }
+}
+
+/// Keys in the top or bottom row of the board are on the top or bottom edge, keys without another key beside them
+/// on their left or right are on that edge. Gaps in the function row do not count, the keys below them are still
+/// surrounded on the board.
+fn board_edges(keys: &[Key]) -> Vec<Edges> {
+    const EPSILON: f32 = 0.01;
+    let top = keys.iter().map(|key| key.y).fold(f32::MAX, f32::min);
+    let bottom = keys
+        .iter()
+        .map(|key| key.y + key.height)
+        .fold(f32::MIN, f32::max);
+    let beside =
+        |a: &Key, b: &Key| a.y < b.y + b.height - EPSILON && b.y < a.y + a.height - EPSILON;
+
+    keys.iter()
+        .map(|key| Edges {
+            top: key.y - top < EPSILON,
+            bottom: bottom - (key.y + key.height) < EPSILON,
+            left: !keys
+                .iter()
+                .any(|other| beside(key, other) && other.x < key.x - EPSILON),
+            right: !keys
+                .iter()
+                .any(|other| beside(key, other) && other.x > key.x + EPSILON),
+        })
+        .collect()
+}
+
+fn center(key: &Key) -> (f32, f32) {
+    (key.x + key.width / 2.0, key.y + key.height / 2.0)
+}
+
+/// Blends the edge keys in line with an inner key, weighted by how close each edge is. The left and right edge keys
+/// are the nearest ones beside it, the top and bottom edge keys are the ones most directly above and below it.
+fn interpolate(edge_keys: &[(&Key, Edges, Rgba<u8>)], key: &Key) -> Rgba<u8> {
+    let (x, y) = center(key);
+    // Distance to the edge key picked by the smallest `rank` for an edge, None when there is none
+    let find = |on_edge: fn(&Edges) -> bool,
+                rank: &dyn Fn(&Key) -> Option<f32>,
+                distance: &dyn Fn(&Key) -> f32| {
+        edge_keys
+            .iter()
+            .filter(|(_, edge, _)| on_edge(edge))
+            .filter_map(|(other, _, color)| Some((rank(other)?, *other, *color)))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, other, color)| (distance(other), color))
+    };
+    let beside = |other: &Key| other.y <= y && y <= other.y + other.height;
+    let below = |other: &Key| Some((center(other).0 - x).abs());
+
+    let left = find(
+        |edge| edge.left,
+        &|other| beside(other).then(|| x - center(other).0),
+        &|other| x - center(other).0,
+    );
+    let right = find(
+        |edge| edge.right,
+        &|other| beside(other).then(|| center(other).0 - x),
+        &|other| center(other).0 - x,
+    );
+    let top = find(|edge| edge.top, &below, &|other| y - center(other).1);
+    let bottom = find(|edge| edge.bottom, &below, &|other| center(other).1 - y);
+
+    let channels = |color: Rgba<u8>| [0, 1, 2].map(|channel| color.0[channel] as f32);
+    // Each axis blends its two edges, the axis with the closer edge decides most of the color
+    let axes = [(left, right), (top, bottom)].map(|edges| match edges {
+        (Some((near, a)), Some((far, b))) => {
+            let t = near / (near + far).max(f32::EPSILON);
+            let (a, b) = (channels(a), channels(b));
+            let color = [0, 1, 2].map(|channel| a[channel] * (1.0 - t) + b[channel] * t);
+            Some((color, 1.0 / near.min(far).max(0.5)))
+        }
+        (Some((distance, color)), None) | (None, Some((distance, color))) => {
+            Some((channels(color), 1.0 / distance.max(0.5)))
+        }
+        (None, None) => None,
+    });
+
+    let mut total = [0.0; 3];
+    let mut weights = 0.0;
+    for (color, weight) in axes.into_iter().flatten() {
+        for (total, channel) in total.iter_mut().zip(color) {
+            *total += channel * weight;
+        }
+        weights += weight;
+    }
+
+    let [r, g, b] = total.map(|channel| (channel / f32::max(weights, f32::EPSILON)).round() as u8);
+    Rgba([r, g, b, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{
+        definitions::{add_definition, DeviceDefinition},
+        layout,
+    };
+
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const UNLIT: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    /// A black screen with the right tenth blue
+    fn right_band_screen() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(200, 100, |x, _| match x >= 180 {
+            true => BLUE,
+            false => Rgba([0, 0, 0, 255]),
+        }))
+    }
+
+    fn sample(keys: &KeyLayout, size: (u32, u32), first_row: u8) -> RgbaImage {
+        let settings = EdgeSampling {
+            depth: 0.1,
+            inner: InnerKeys::Off,
+        };
+        sample_edges(
+            &right_band_screen(),
+            (0, 0, 200, 100),
+            size,
+            first_row,
+            keys,
+            settings,
+        )
+        .to_rgba8()
+    }
+
+    #[test]
+    fn follows_the_edge_of_the_layout() {
+        let keys = layout::sixty_percent().clip((14, 5), 1);
+        let output = sample(&keys, (14, 5), 1);
+
+        // Backspace, enter and right shift end their rows
+        assert_eq!(output.get_pixel(13, 0), &BLUE);
+        assert_eq!(output.get_pixel(13, 2), &BLUE);
+        assert_eq!(output.get_pixel(13, 3), &BLUE);
+        // The key before enter is inside the board
+        assert_eq!(output.get_pixel(12, 2), &UNLIT);
+        // Nothing is on column 3 of the bottom row, the spacebar starts on column 4
+        assert_eq!(output.get_pixel(3, 4), &UNLIT);
+    }
+
+    #[test]
+    fn skips_masked_leds() {
+        add_definition(DeviceDefinition {
+            name: "Masked Test Board".to_string(),
+            models: vec!["Masked Test Board".to_string()],
+            rgb_size: (4, 3),
+            first_row: 0,
+            layout: None,
+            led_mask: vec![(0, 3)],
+            quirks: Vec::new(),
+            firmware: Vec::new(),
+        });
+        let keys = layout::lit_keys("Masked Test Board", (4, 3), 0);
+        assert_eq!(keys.keys.len(), 11);
+
+        // The key before the missing led ends the top row instead
+        let output = sample(&keys, (4, 3), 0);
+        assert_eq!(output.get_pixel(3, 0), &UNLIT);
+        assert_eq!(output.get_pixel(2, 0), &BLUE);
+        assert_eq!(output.get_pixel(3, 1), &BLUE);
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+/// Color temperature the screen is assumed to have, a white point at this temperature leaves white alone
+const NEUTRAL_WHITE_POINT: u32 = 6500;
+
+/// Per channel correction that makes the colors of a keyboard match the screen, found with the calibration wizard
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub gain: [f32; 3],
+    pub gamma: [f32; 3],
+    /// Color temperature of white on the keyboard in kelvin
+    pub white_point: u32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            gain: [1.0; 3],
+            gamma: [1.0; 3],
+            white_point: NEUTRAL_WHITE_POINT,
+        }
+    }
+}
+
+impl Calibration {
+    /// The fixed correction the Red Shift Fix used to apply, a starting point for boards with stock keycaps
+    pub const RED_SHIFT: Calibration = Calibration {
+        gain: [0.55, 1.0, 1.2],
+        gamma: [1.0; 3],
+        white_point: NEUTRAL_WHITE_POINT,
+    };
+
+    /// Corrects a color with channels from 0 to 255
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        let white = white_balance(self.white_point);
+        [0, 1, 2].map(|channel| {
+            (color[channel].max(0.0) / 255.0).powf(self.gamma[channel])
+                * 255.0
+                * self.gain[channel]
+                * white[channel]
+        })
+    }
+}
+
+/// Reference color the calibration wizard shows on a device in place of the capture, with the calibration being tried
+#[derive(Clone, Copy)]
+pub struct CalibrationPreview {
+    pub device: usize,
+    pub color: [u8; 3],
+    pub calibration: Calibration,
+}
+
+/// Channel multipliers that turn white into the color of a black body at `kelvin`, none of them go over 1
+fn white_balance(kelvin: u32) -> [f32; 3] {
+    let target = black_body(kelvin);
+    let neutral = black_body(NEUTRAL_WHITE_POINT);
+    let balance = [0, 1, 2].map(|channel| target[channel] / neutral[channel]);
+    let brightest = balance.iter().cloned().fold(f32::EPSILON, f32::max);
+
+    balance.map(|channel| channel / brightest)
+}
+
+/// Approximate color of a black body at `kelvin`, the curve fit holds from 1000K to 40000K
+fn black_body(kelvin: u32) -> [f32; 3] {
+    let temperature = kelvin.clamp(1000, 40000) as f32 / 100.0;
+
+    let red = match temperature <= 66.0 {
+        true => 255.0,
+        false => 329.69873 * (temperature - 60.0).powf(-0.13320476),
+    };
+    let green = match temperature <= 66.0 {
+        true => 99.4708 * temperature.ln() - 161.11957,
+        false => 288.12216 * (temperature - 60.0).powf(-0.07551485),
+    };
+    let blue = match temperature {
+        temperature if temperature >= 66.0 => 255.0,
+        temperature if temperature <= 19.0 => 0.0,
+        temperature => 138.51773 * (temperature - 10.0).ln() - 305.0448,
+    };
+
+    [red, green, blue].map(|channel| channel.clamp(1.0, 255.0))
+}
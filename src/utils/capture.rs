@@ -1,43 +1,110 @@
 use std::{
+    collections::HashMap,
     sync::{atomic::AtomicBool, RwLock},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::wooting;
-use image::{imageops::FilterType, DynamicImage, GenericImageView};
-use scorched::{LogExpect, LogImportance};
+use crate::{
+    utils::{
+        ambilight::{self, EdgeSampling},
+        calibration::{Calibration, CalibrationPreview},
+        change::ChangeDetector,
+        config::OpenRgbConfig,
+        device::RgbDevice,
+        downscale::{AreaDownscaler, DownscaleMethod, Region},
+        hotplug,
+        interpolation::Interpolator,
+        layout,
+        letterbox::LetterboxDetector,
+        monitor::MonitorIdentity,
+        openrgb::OpenRgbOutput,
+        pipeline::{ColorPipeline, StageConfig, StageKind},
+        recording::RecordingDevice,
+        source::{CaptureError, CaptureSource, Frame, SourceKind},
+    },
+    wooting,
+};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use scorched::{logf, LogData, LogImportance};
 use std::sync::atomic::Ordering;
-use xcap::Monitor;
 
 #[derive(Clone)]
 pub struct CaptureSettings {
-    pub screen_index: usize,
-    pub downscale_method: FilterType,
     pub capture_frame_limit: u32,
-    pub reduce_bright_effects: bool,
-    pub red_shift_fix: bool,
-    pub highlight_wasd: bool,
+    /// Rate the devices are drawn at between captures while blending towards the newest frame, None draws each
+    /// frame once
+    pub interpolation_rate: Option<u32>,
+    pub display_rgb_preview: bool,
+    pub devices: Vec<DeviceSettings>,
+    pub openrgb: OpenRgbConfig,
+}
+
+/// Settings for a single device, indexed the same way as the devices handed to `capture`
+#[derive(Clone)]
+pub struct DeviceSettings {
+    /// Captured screen, the primary screen when None
+    pub monitor: Option<MonitorIdentity>,
+    /// Captures the virtual desktop made of every screen instead of `monitor`
+    pub span_screens: bool,
+    pub downscale_method: DownscaleMethod,
+    pub key_geometry: bool,
     pub brightness: u8,
     pub device_name: String,
     pub rgb_size: (u32, u32),
-    pub display_rgb_preview: bool,
+    /// Part of the screen that is captured as (x, y, width, height) fractions of the screen, the whole screen when None
+    pub crop: Option<(f32, f32, f32, f32)>,
+    /// Title or app name of a window to capture instead of the screen, the screen is used while it is not found
+    pub capture_window: Option<String>,
+    /// Crops away black bars found by a `LetterboxDetector` before downscaling
+    pub remove_black_bars: bool,
+    /// Lights the edge of the keyboard from the edges of the screen, replaces the downscale method and key geometry
+    pub edge_sampling: Option<EdgeSampling>,
+    pub color_stages: Vec<StageConfig>,
+    pub calibration: Calibration,
 }
 
+/// First wait before capturing a failed source again, doubled after every failure up to `RETRY_MAX`
+const RETRY_MIN: Duration = Duration::from_millis(500);
+const RETRY_MAX: Duration = Duration::from_secs(8);
+
+/// A source that failed, it is not captured again until `next_attempt`
+struct Retry {
+    error: CaptureError,
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+/// Longest side of the screen thumbnails in pixels
+const THUMBNAIL_SIZE: u32 = 256;
+
 pub static CAPTURE_SETTINGS_RELOAD: AtomicBool = AtomicBool::new(false);
 pub static CAPTURE_SETTINGS: RwLock<CaptureSettings> = RwLock::new(CaptureSettings {
-    screen_index: 0,
-    downscale_method: FilterType::Triangle,
-    reduce_bright_effects: false,
-    red_shift_fix: false,
-    highlight_wasd: false,
-    brightness: 100,
     capture_frame_limit: 10,
-    device_name: String::new(),
-    rgb_size: (0, 0),
+    interpolation_rate: None,
     display_rgb_preview: false,
+    devices: Vec::new(),
+    openrgb: OpenRgbConfig {
+        enabled: false,
+        host: String::new(),
+        port: 6742,
+        targets: Vec::new(),
+    },
 });
+/// Why each device is not being captured, None while capturing works
+pub static CAPTURE_STATUS: RwLock<Vec<Option<CaptureError>>> = RwLock::new(Vec::new());
 pub static CAPTURE_LOCK: AtomicBool = AtomicBool::new(false);
-pub static CAPTURE_PREVIEW: RwLock<Option<DynamicImage>> = RwLock::new(None);
+pub static CAPTURE_PREVIEW: RwLock<Vec<Option<DynamicImage>>> = RwLock::new(Vec::new());
+/// Small uncropped image of the screen of each device, only updated while the UI keeps requesting it
+pub static CAPTURE_THUMBNAIL: RwLock<Vec<Option<DynamicImage>>> = RwLock::new(Vec::new());
+pub static CAPTURE_THUMBNAIL_REQUEST: AtomicBool = AtomicBool::new(false);
+/// Part of an image as (x, y, width, height) fractions of its size
+pub type Area = (f32, f32, f32, f32);
+
+/// Shown on a device instead of its capture while the calibration wizard is open
+pub static CALIBRATION_PREVIEW: RwLock<Option<CalibrationPreview>> = RwLock::new(None);
+
+/// Part of the capture of each device left after removing black bars
+pub static CAPTURE_LETTERBOX: RwLock<Vec<Option<Area>>> = RwLock::new(Vec::new());
 //pub static CAPTURE_PREVIEW: LazyLock<DynamicImage> = LazyLock::new(|| {
 //    let img = image::ImageBuffer::new(1, 1);
 //    image::DynamicImage::ImageRgba8(img)
@@ -50,26 +117,42 @@ pub static CAPTURE_PREVIEW: RwLock<Option<DynamicImage>> = RwLock::new(None);
 //    });
 //}
 
-pub fn capture() {
+/// Runs the capture loop forever, pushing every processed frame to each of the `devices`.
+///
+/// Every device captures from `source_override` when it is given instead of the source picked in its settings.
+pub fn capture(mut devices: Vec<Box<dyn RgbDevice>>, source_override: Option<SourceKind>) {
     let mut current_settings = CaptureSettings {
-        screen_index: 0,
-        downscale_method: FilterType::Triangle,
-        reduce_bright_effects: false,
-        red_shift_fix: false,
-        highlight_wasd: false,
-        brightness: 100,
         capture_frame_limit: 10,
-        device_name: wooting::get_device_name(),
-        rgb_size: (0, 0),
+        interpolation_rate: None,
         display_rgb_preview: false,
+        devices: Vec::new(),
+        openrgb: OpenRgbConfig::default(),
     };
-    let mut last_frame = DynamicImage::new_rgba8(1, 1);
+    let mut openrgb = OpenRgbOutput::new(current_settings.openrgb.clone());
+    // Sources stay open between frames so windows and image sequences keep their place
+    let mut sources: HashMap<SourceKind, Box<dyn CaptureSource>> = HashMap::new();
+    let mut retries: HashMap<SourceKind, Retry> = HashMap::new();
+    let mut letterbox_detectors: Vec<Option<LetterboxDetector>> = Vec::new();
+    let mut change_detectors: Vec<ChangeDetector> = Vec::new();
+    let mut area_downscalers: Vec<AreaDownscaler> = Vec::new();
+    // Built from the settings when first needed, stages keep their state until other stages are enabled
+    let mut pipelines: Vec<Option<ColorPipeline>> = Vec::new();
+    let mut outputs: Vec<Interpolator> = Vec::new();
     let mut next_frame: Duration;
+    let device_events = hotplug::subscribe();
+
+    // Model and lighting size of each device, kept up to date by the hotplug events and applied over the UI settings
+    let mut device_states: Vec<(String, (u32, u32))> = devices
+        .iter_mut()
+        .map(|device| {
+            device.connect();
+            (device.name(), device.rgb_size().unwrap_or((0, 0)))
+        })
+        .collect();
 
-    CAPTURE_PREVIEW
-        .write()
-        .unwrap()
-        .replace(DynamicImage::new_rgba8(1, 1));
+    *CAPTURE_PREVIEW.write().unwrap() = vec![None; devices.len()];
+    *CAPTURE_LETTERBOX.write().unwrap() = vec![None; devices.len()];
+    *CAPTURE_STATUS.write().unwrap() = vec![None; devices.len()];
 
     loop {
         if CAPTURE_LOCK.load(Ordering::Relaxed) {
@@ -80,85 +163,502 @@ pub fn capture() {
         if CAPTURE_SETTINGS_RELOAD.load(Ordering::Relaxed) {
             current_settings = CAPTURE_SETTINGS.read().unwrap().clone();
             CAPTURE_SETTINGS_RELOAD.store(false, Ordering::Relaxed);
+            apply_device_states(&device_states, &mut current_settings);
+            openrgb.configure(current_settings.openrgb.clone());
+
+            // New settings are shown right away instead of waiting for the screen to change
+            change_detectors.clear();
+            for (pipeline, device_settings) in pipelines
+                .iter_mut()
+                .zip(current_settings.devices.iter())
+                .filter_map(|(pipeline, device_settings)| {
+                    Some((pipeline.as_mut()?, device_settings))
+                })
+            {
+                pipeline.update(
+                    &device_settings.color_stages,
+                    device_settings.brightness,
+                    device_settings.calibration,
+                );
+            }
+
+            let used = |kind: &SourceKind| {
+                current_settings.devices.iter().any(|device_settings| {
+                    device_sources(device_settings, source_override.as_ref()).contains(kind)
+                })
+            };
+            sources.retain(|kind, _| used(kind));
+            retries.retain(|kind, _| used(kind));
+        }
+
+        // Every event is handled the same way, the device info already holds the new list
+        if device_events.try_iter().count() > 0 {
+            let previous_states = device_states.clone();
+            sync_devices(&mut devices, &mut device_states);
+            apply_device_states(&device_states, &mut current_settings);
+
+            // Forces every device to be drawn again even if the screen has not changed, only the pipelines of
+            // indexes that now hold another device start over
+            change_detectors.clear();
+            for (index, pipeline) in pipelines.iter_mut().enumerate() {
+                if previous_states.get(index) != device_states.get(index) {
+                    *pipeline = None;
+                }
+            }
+            *CAPTURE_PREVIEW.write().unwrap() = vec![None; devices.len()];
+            *CAPTURE_LETTERBOX.write().unwrap() = vec![None; devices.len()];
+            CAPTURE_STATUS.write().unwrap().resize(devices.len(), None);
         }
 
-        let mut current_frame_reduce = false;
+        // Each source is only captured once per frame, even when several devices follow it
+        let mut frames: HashMap<SourceKind, Result<Frame, CaptureError>> = HashMap::new();
+        let capture_thumbnails = CAPTURE_THUMBNAIL_REQUEST.swap(false, Ordering::Relaxed);
+        if capture_thumbnails {
+            CAPTURE_THUMBNAIL
+                .write()
+                .unwrap()
+                .resize(devices.len(), None);
+        }
 
-        let monitors = Monitor::all().unwrap();
-        let capture = monitors[current_settings.screen_index]
-            .capture_image()
-            .unwrap();
+        letterbox_detectors.resize_with(devices.len(), || None);
+        change_detectors.resize_with(devices.len(), ChangeDetector::default);
+        area_downscalers.resize_with(devices.len(), AreaDownscaler::default);
+        pipelines.resize_with(devices.len(), || None);
+        outputs.resize_with(devices.len(), Interpolator::default);
 
-        let img = image::DynamicImage::ImageRgba8(
-            image::ImageBuffer::from_raw(capture.width(), capture.height(), capture.to_vec())
-                .log_expect(
-                    LogImportance::Error,
-                    "Failed to convert capture to image buffer",
-                ),
+        next_frame = Duration::from_millis(
+            ((1.0 / current_settings.capture_frame_limit as f32) * 1000.0).round() as u64,
         );
+        let interpolation_rate = current_settings
+            .interpolation_rate
+            .filter(|rate| *rate > current_settings.capture_frame_limit);
+        // How long each new frame is blended in over, the whole time until the next one when interpolating
+        let blend = match interpolation_rate {
+            Some(_) => next_frame,
+            None => Duration::ZERO,
+        };
 
-        // If the image is the same as the last frame, we don't need to process it
-        if img == last_frame {
-            next_frame = Duration::from_millis(
-                ((1.0 / current_settings.capture_frame_limit as f32) * 1000.0).round() as u64,
+        for (index, device) in devices.iter_mut().enumerate() {
+            let Some(device_settings) = current_settings.devices.get(index) else {
+                continue;
+            };
+
+            // Output is paused while the device is unplugged or has no lighting
+            if device_settings.rgb_size.0 == 0 || device_settings.rgb_size.1 == 0 {
+                continue;
+            }
+
+            let calibration_preview = CALIBRATION_PREVIEW
+                .read()
+                .unwrap()
+                .filter(|preview| preview.device == index);
+            if let Some(preview) = calibration_preview {
+                let [r, g, b] = preview.color;
+                let (width, height) = device_settings.rgb_size;
+                let reference = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                    width,
+                    height,
+                    Rgba([r, g, b, 255]),
+                ));
+                let stage = StageConfig {
+                    stage: StageKind::Calibration,
+                    enabled: true,
+                };
+                wooting::draw_rgb(
+                    device.as_mut(),
+                    &reference,
+                    &mut ColorPipeline::new(&[stage], 100, preview.calibration),
+                    device_settings.device_name.clone(),
+                );
+
+                // The capture is drawn again as soon as the wizard closes
+                change_detectors[index] = ChangeDetector::default();
+                continue;
+            }
+
+            // Falls back to the next source while one has no image, like a window that is minimized
+            let kinds = device_sources(device_settings, source_override.as_ref());
+            let found = kinds
+                .iter()
+                .find(|kind| capture_source(kind, &mut sources, &mut frames, &mut retries));
+            set_status(
+                index,
+                match found {
+                    Some(_) => None,
+                    None => kinds
+                        .last()
+                        .and_then(|kind| frames.get(kind)?.as_ref().err().cloned()),
+                },
             );
-            std::thread::sleep(next_frame - Duration::from_millis(1));
-            continue;
-        }
 
-        last_frame = img.clone();
+            let Some(source) = found
+                .and_then(|kind| frames.get(kind)?.as_ref().ok())
+                .map(|frame| &frame.image)
+            else {
+                continue;
+            };
 
-        let rgb_screen = img.resize_exact(
-            current_settings.rgb_size.0,
-            current_settings.rgb_size.1,
-            current_settings.downscale_method,
-        );
+            if capture_thumbnails {
+                CAPTURE_THUMBNAIL.write().unwrap()[index] =
+                    Some(source.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE));
+            }
 
-        if current_settings.display_rgb_preview {
-            CAPTURE_PREVIEW.write().unwrap().replace(rgb_screen.clone());
-            //*CAPTURE_PREVIEW.write().unwrap() = rgb_screen.clone();
-        }
+            // Part of the source that is shown, the source itself is only copied by the filter downscale methods
+            let region = match device_settings.crop {
+                Some(crop) => crop_region(source, crop),
+                None => (0, 0, source.width(), source.height()),
+            };
 
-        let frame_rgb_size: (u32, u32) = current_settings.rgb_size;
-        if frame_rgb_size.0 != 0 && frame_rgb_size.1 != 0 {
-            let resized_capture = rgb_screen.clone();
+            let detector = &mut letterbox_detectors[index];
+            let region = match (device_settings.remove_black_bars, detector.as_mut()) {
+                (true, Some(detector)) => {
+                    let (x, y, width, height) =
+                        detector.update(&*source.view(region.0, region.1, region.2, region.3));
+                    let (region_width, region_height) = (region.2 as f32, region.3 as f32);
+                    CAPTURE_LETTERBOX.write().unwrap()[index] = Some((
+                        x as f32 / region_width,
+                        y as f32 / region_height,
+                        width as f32 / region_width,
+                        height as f32 / region_height,
+                    ));
+                    (region.0 + x, region.1 + y, width, height)
+                }
+                (true, None) => {
+                    *detector = Some(LetterboxDetector::default());
+                    region
+                }
+                (false, _) => {
+                    if detector.take().is_some() {
+                        CAPTURE_LETTERBOX.write().unwrap()[index] = None;
+                    }
+                    region
+                }
+            };
 
-            if current_settings.reduce_bright_effects {
-                let avg_screen =
-                    resized_capture
-                        .clone()
-                        .resize(1, 1, image::imageops::FilterType::Gaussian);
+            // If the image looks the same as the last frame, we don't need to process it
+            if !change_detectors[index].changed(source, region) {
+                // Smoothing keeps fading towards the last frame while the screen stands still
+                if pipelines[index]
+                    .as_ref()
+                    .is_some_and(|pipeline| !pipeline.settled())
+                {
+                    draw_output(
+                        device.as_mut(),
+                        device_settings,
+                        &mut pipelines[index],
+                        &mut outputs[index],
+                        &mut openrgb,
+                        index,
+                        blend,
+                    );
+                }
+                continue;
+            }
 
-                let image::Rgba([r, g, b, _]) = avg_screen.get_pixel(0, 0);
+            let first_row = device.first_row();
+            let layout = layout::get_layout(&device_settings.device_name)
+                .filter(|_| device_settings.key_geometry)
+                .map(|layout| layout.clip(device_settings.rgb_size, first_row));
+            let filtered;
+            let rgb_screen = match (
+                device_settings.edge_sampling,
+                device_settings.downscale_method,
+            ) {
+                (Some(edge_sampling), _) => {
+                    let keys = layout::lit_keys(
+                        &device_settings.device_name,
+                        device_settings.rgb_size,
+                        first_row,
+                    );
+                    filtered = ambilight::sample_edges(
+                        source,
+                        region,
+                        device_settings.rgb_size,
+                        first_row,
+                        &keys,
+                        edge_sampling,
+                    );
+                    &filtered
+                }
+                (None, DownscaleMethod::Area) => area_downscalers[index].downscale(
+                    source,
+                    region,
+                    device_settings.rgb_size,
+                    layout.as_ref().map(|layout| (layout, first_row)),
+                ),
+                (None, DownscaleMethod::Filter(filter)) => {
+                    let img = source.crop_imm(region.0, region.1, region.2, region.3);
+                    filtered = match layout {
+                        Some(layout) => layout::sample_layout(
+                            &img,
+                            &layout,
+                            device_settings.rgb_size,
+                            first_row,
+                            filter,
+                        ),
+                        None => img.resize_exact(
+                            device_settings.rgb_size.0,
+                            device_settings.rgb_size.1,
+                            filter,
+                        ),
+                    };
+                    &filtered
+                }
+            };
 
-                if r > 220 || g > 220 || b > 220 {
-                    current_frame_reduce = true;
-                    current_settings.brightness -= 50;
+            if current_settings.display_rgb_preview {
+                if let Some(preview) = CAPTURE_PREVIEW.write().unwrap().get_mut(index) {
+                    update_preview(preview, rgb_screen);
                 }
             }
 
-            wooting::draw_rgb(
-                resized_capture.clone(),
-                current_settings.brightness,
-                current_settings.red_shift_fix,
-                current_settings.highlight_wasd,
-                current_settings.device_name.clone(),
+            outputs[index].push(rgb_screen);
+            draw_output(
+                device.as_mut(),
+                device_settings,
+                &mut pipelines[index],
+                &mut outputs[index],
+                &mut openrgb,
+                index,
+                blend,
             );
+        }
+
+        // Time spent capturing and processing counts towards the frame
+        let elapsed = frames
+            .values()
+            .flatten()
+            .map(|frame| frame.timestamp.elapsed())
+            .max()
+            .unwrap_or_default();
+        let wake = Instant::now()
+            + next_frame
+                .saturating_sub(elapsed)
+                .saturating_sub(Duration::from_millis(1));
 
-            if current_frame_reduce {
-                current_settings.brightness += 50;
+        // Devices are drawn again between captures with the blend moving towards the newest frame
+        if let Some(rate) = interpolation_rate {
+            let tick = Duration::from_secs_f32(1.0 / rate as f32);
+            while Instant::now() + tick < wake {
+                std::thread::sleep(tick);
+
+                let calibrating = CALIBRATION_PREVIEW
+                    .read()
+                    .unwrap()
+                    .map(|preview| preview.device);
+                for (index, device) in devices.iter_mut().enumerate() {
+                    let Some(device_settings) = current_settings.devices.get(index) else {
+                        continue;
+                    };
+                    if device_settings.rgb_size.0 == 0
+                        || device_settings.rgb_size.1 == 0
+                        || calibrating == Some(index)
+                    {
+                        continue;
+                    }
+
+                    draw_output(
+                        device.as_mut(),
+                        device_settings,
+                        &mut pipelines[index],
+                        &mut outputs[index],
+                        &mut openrgb,
+                        index,
+                        blend,
+                    );
+                }
+            }
+        }
+
+        std::thread::sleep(wake.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Draws the current blend of the frames of a device through its color pipeline, which is built when first needed.
+/// OpenRGB gets the same lighting as the device at `index`, after every stage of its pipeline.
+fn draw_output(
+    device: &mut dyn RgbDevice,
+    device_settings: &DeviceSettings,
+    pipeline: &mut Option<ColorPipeline>,
+    output: &mut Interpolator,
+    openrgb: &mut OpenRgbOutput,
+    index: usize,
+    blend: Duration,
+) {
+    let Some(frame) = output.frame(blend) else {
+        return;
+    };
+    let pipeline = pipeline.get_or_insert_with(|| {
+        ColorPipeline::new(
+            &device_settings.color_stages,
+            device_settings.brightness,
+            device_settings.calibration,
+        )
+    });
+
+    let sent = wooting::draw_rgb(device, frame, pipeline, device_settings.device_name.clone());
+    openrgb.send(index, &sent, device_settings.rgb_size, device.first_row());
+}
+
+/// Points every device at the device now at its SDK index, pausing the indexes nothing is plugged into anymore
+fn sync_devices(
+    devices: &mut Vec<Box<dyn RgbDevice>>,
+    device_states: &mut Vec<(String, (u32, u32))>,
+) {
+    let device_info = wooting::device_info();
+
+    while devices.len() < device_info.len() {
+        devices.push(Box::new(RecordingDevice::new(Box::new(
+            wooting::WootingDevice::new(devices.len() as u8),
+        ))));
+        device_states.push(("N/A".to_string(), (0, 0)));
+    }
+
+    for (index, device) in devices.iter_mut().enumerate() {
+        device_states[index] = match device_info.get(index) {
+            Some(info) => {
+                device.connect();
+                (info.model.clone(), info.rgb_size)
+            }
+            None => {
+                device.disconnect();
+                ("N/A".to_string(), (0, 0))
+            }
+        };
+    }
+}
+
+/// Sources a device captures from in order of preference
+fn device_sources(
+    device_settings: &DeviceSettings,
+    source_override: Option<&SourceKind>,
+) -> Vec<SourceKind> {
+    if let Some(source) = source_override {
+        return vec![source.clone()];
+    }
+
+    let screen = match device_settings.span_screens {
+        true => SourceKind::Desktop,
+        false => SourceKind::Monitor(device_settings.monitor.clone()),
+    };
+
+    match device_settings
+        .capture_window
+        .as_deref()
+        .filter(|query| !query.is_empty())
+    {
+        Some(query) => vec![SourceKind::Window(query.to_string()), screen],
+        None => vec![screen],
+    }
+}
+
+/// Takes the next frame of the source into `frames` unless it was already captured this frame, opening the source on
+/// first use. Sources that fail are left alone for a while before trying them again. Returns whether the source has
+/// an image.
+fn capture_source(
+    kind: &SourceKind,
+    sources: &mut HashMap<SourceKind, Box<dyn CaptureSource>>,
+    frames: &mut HashMap<SourceKind, Result<Frame, CaptureError>>,
+    retries: &mut HashMap<SourceKind, Retry>,
+) -> bool {
+    if let Some(result) = frames.get(kind) {
+        return result.is_ok();
+    }
+
+    let waiting = retries
+        .get(kind)
+        .filter(|retry| Instant::now() < retry.next_attempt)
+        .map(|retry| retry.error.clone());
+    let result = match waiting {
+        Some(error) => Err(error),
+        None => {
+            let result = sources
+                .entry(kind.clone())
+                .or_insert_with(|| kind.open())
+                .next_frame();
+
+            match &result {
+                Ok(_) => {
+                    if let Some(retry) = retries.remove(kind) {
+                        logf!(Info, "Capturing again after: {}", retry.error);
+                    }
+                }
+                // Windows are searched for on their own schedule while the screen is captured instead
+                Err(CaptureError::WindowNotFound(_)) => {}
+                Err(error) => {
+                    let retry = retries.get(kind);
+                    let delay = retry
+                        .map(|retry| (retry.delay * 2).min(RETRY_MAX))
+                        .unwrap_or(RETRY_MIN);
+                    if retry.is_none_or(|retry| retry.error != *error) {
+                        logf!(Warning, "{}, retrying in {:?}", error, delay);
+                    }
+
+                    // Opened again on the next attempt in case whatever it was holding on to is gone for good
+                    sources.remove(kind);
+                    retries.insert(
+                        kind.clone(),
+                        Retry {
+                            error: error.clone(),
+                            delay,
+                            next_attempt: Instant::now() + delay,
+                        },
+                    );
+                }
             }
-        } else {
-            wooting::reconnect_device();
-            current_settings.device_name = wooting::get_device_name();
 
-            current_settings.rgb_size = wooting::get_rgb_size()
-                .log_expect(scorched::LogImportance::Error, "Failed to get rgb size");
+            result
         }
+    };
 
-        next_frame = Duration::from_millis(
-            ((1.0 / current_settings.capture_frame_limit as f32) * 1000.0).round() as u64,
-        );
-        std::thread::sleep(next_frame - Duration::from_millis(1));
+    let captured = result.is_ok();
+    frames.insert(kind.clone(), result);
+    captured
+}
+
+/// Updates the capture status of a device, only taking the lock for writing when it changed
+fn set_status(index: usize, status: Option<CaptureError>) {
+    if CAPTURE_STATUS.read().unwrap().get(index) != Some(&status) {
+        if let Some(current) = CAPTURE_STATUS.write().unwrap().get_mut(index) {
+            *current = status;
+        }
+    }
+}
+
+fn apply_device_states(
+    device_states: &[(String, (u32, u32))],
+    current_settings: &mut CaptureSettings,
+) {
+    for (device_settings, (model, rgb_size)) in current_settings
+        .devices
+        .iter_mut()
+        .zip(device_states.iter())
+    {
+        device_settings.device_name = model.clone();
+        device_settings.rgb_size = *rgb_size;
+    }
+}
+
+/// Cuts the part given as (x, y, width, height) fractions out of the screen, always at least one pixel
+fn crop_region(img: &DynamicImage, crop: Area) -> Region {
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    let x = ((crop.0.clamp(0.0, 1.0) * width) as u32).min(img.width() - 1);
+    let y = ((crop.1.clamp(0.0, 1.0) * height) as u32).min(img.height() - 1);
+    let crop_width = ((crop.2.clamp(0.0, 1.0) * width) as u32).clamp(1, img.width() - x);
+    let crop_height = ((crop.3.clamp(0.0, 1.0) * height) as u32).clamp(1, img.height() - y);
+
+    (x, y, crop_width, crop_height)
+}
+
+/// Copies the frame into the preview, reusing the preview image while it has the same size
+fn update_preview(preview: &mut Option<DynamicImage>, frame: &DynamicImage) {
+    match (
+        preview.as_mut().and_then(|preview| preview.as_mut_rgba8()),
+        frame.as_rgba8(),
+    ) {
+        (Some(preview), Some(frame)) if preview.dimensions() == frame.dimensions() => {
+            preview.copy_from_slice(frame)
+        }
+        _ => *preview = Some(frame.clone()),
     }
 }
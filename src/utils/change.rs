@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, GenericImageView};
+
+/// Pixels sampled along each side of a frame, about one per 30 pixels of a 4K screen
+const SAMPLES: (u32, u32) = (128, 72);
+/// Longest a frame is skipped for, changes that fall between the sampled pixels show up after at most this long
+const FORCED_REFRESH: Duration = Duration::from_secs(1);
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hash of the size of a region given as (x, y, width, height) and a sparse grid of its pixels, regions with the same
+/// signature are very likely the same
+pub fn frame_signature(img: &DynamicImage, region: (u32, u32, u32, u32)) -> u64 {
+    let (left, top, width, height) = region;
+    let columns = SAMPLES.0.min(width);
+    let rows = SAMPLES.1.min(height);
+
+    let mut hash = FNV_OFFSET;
+    let mut add = |value: u32| hash = (hash ^ value as u64).wrapping_mul(FNV_PRIME);
+    for value in [left, top, width, height] {
+        add(value);
+    }
+
+    // Samples the middle of each cell of the grid, reading the buffer directly when it is already RGBA
+    let raw = img.as_rgba8().map(|buffer| buffer.as_raw());
+    for row in 0..rows {
+        let y = top + (row * 2 + 1) * height / (rows * 2);
+        for column in 0..columns {
+            let x = left + (column * 2 + 1) * width / (columns * 2);
+            let pixel = match raw {
+                Some(raw) => {
+                    let start = (y as usize * img.width() as usize + x as usize) * 4;
+                    u32::from_le_bytes([raw[start], raw[start + 1], raw[start + 2], raw[start + 3]])
+                }
+                None => u32::from_le_bytes(img.get_pixel(x, y).0),
+            };
+            add(pixel);
+        }
+    }
+
+    hash
+}
+
+/// Decides whether a frame is worth processing by comparing its signature to the last processed one
+#[derive(Default)]
+pub struct ChangeDetector {
+    signature: Option<u64>,
+    last_refresh: Option<Instant>,
+}
+
+impl ChangeDetector {
+    /// True when `region` of `img` looks different from the last one let through or that one is older than
+    /// `FORCED_REFRESH`
+    pub fn changed(&mut self, img: &DynamicImage, region: (u32, u32, u32, u32)) -> bool {
+        let signature = frame_signature(img, region);
+        let stale = self
+            .last_refresh
+            .is_none_or(|last_refresh| last_refresh.elapsed() >= FORCED_REFRESH);
+
+        if self.signature == Some(signature) && !stale {
+            return false;
+        }
+
+        self.signature = Some(signature);
+        self.last_refresh = Some(Instant::now());
+        true
+    }
+}
@@ -1,37 +1,197 @@
-use std::fs::File;
-
 use egui_notify::Toasts;
 use image::imageops::FilterType;
 use ron::{
-    de::from_reader,
+    de::from_str,
     ser::{to_string_pretty, PrettyConfig},
 };
 use scorched::*;
 use serde::{Deserialize, Serialize};
 
-use super::paths;
+use super::{
+    ambilight::EdgeSampling,
+    calibration::Calibration,
+    downscale::DownscaleMethod,
+    monitor::MonitorIdentity,
+    paths,
+    pipeline::{self, StageConfig, StageKind},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub config_version: u8,
-    pub brightness: u8,
-    pub reduce_bright_effects: bool,
-    pub screen: usize,
     pub display_rgb_preview: bool,
-    pub downscale_method_index: u8,
     pub frame_limit: (u8, u8),
-    pub red_shift_fix: bool,
-    pub highlight_wasd: bool,
+    /// Rate the lighting is drawn at while blending between captured frames, None draws each frame once
+    pub interpolation_rate: Option<u8>,
     pub dark_mode: bool,
     pub check_updates: bool,
+    pub devices: Vec<DeviceConfig>,
+    pub openrgb: OpenRgbConfig,
+}
+
+/// Per device settings, matched to connected devices by model name
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceConfig {
+    /// Empty for the settings carried over from a version 3 config, the first device without settings of its own
+    /// takes them
+    pub model: String,
+    pub brightness: u8,
+    /// Captured screen, the primary screen when None
+    pub monitor: Option<MonitorIdentity>,
+    /// Captures every screen stitched together at their real positions instead of `screen`
+    pub span_screens: bool,
+    pub downscale_method_index: u8,
+    pub key_geometry: bool,
+    /// Captured part of the screen as (x, y, width, height) fractions of the screen
+    pub crop: Option<(f32, f32, f32, f32)>,
+    /// Title or app name of the window to capture instead of the screen
+    pub capture_window: Option<String>,
+    /// Crops away black bars that stay in place, like the ones around films
+    pub remove_black_bars: bool,
+    /// Samples only bands along the edges of the screen onto the edge of the keyboard instead of the whole screen
+    pub edge_sampling: Option<EdgeSampling>,
+    /// Color processing applied in order between downscaling and the device
+    pub color_stages: Vec<StageConfig>,
+    /// Used by the Calibration stage, set with the calibration wizard
+    pub calibration: Calibration,
+}
+
+impl DeviceConfig {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            brightness: 100,
+            monitor: None,
+            span_screens: false,
+            downscale_method_index: 1,
+            key_geometry: true,
+            crop: None,
+            capture_window: None,
+            remove_black_bars: false,
+            edge_sampling: None,
+            color_stages: pipeline::default_stages(),
+            calibration: Calibration::default(),
+        }
+    }
+}
+
+/// Output to an OpenRGB SDK server, next to the Wooting devices
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct OpenRgbConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub targets: Vec<OpenRgbTarget>,
+}
+
+impl Default for OpenRgbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 6742,
+            targets: Vec::new(),
+        }
+    }
 }
 
-pub static CONFIG_VERSION: u8 = 3;
+/// Sends the frame of the device at index `source` to a controller of the OpenRGB server, matched by name
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct OpenRgbTarget {
+    pub source: usize,
+    pub controller: String,
+    /// Every zone of the controller when None
+    pub zone: Option<String>,
+}
+
+pub static CONFIG_VERSION: u8 = 4;
+
+/// Reads only the version of a config, every other field is ignored
+#[derive(Deserialize)]
+struct ConfigVersion {
+    config_version: u8,
+}
+
+/// Config of version 3, which had a single set of settings for the one device it drove
+#[derive(Deserialize)]
+struct ConfigV3 {
+    brightness: u8,
+    reduce_bright_effects: bool,
+    /// Position in `Monitor::all()`
+    screen: usize,
+    display_rgb_preview: bool,
+    downscale_method_index: u8,
+    frame_limit: (u8, u8),
+    red_shift_fix: bool,
+    highlight_wasd: bool,
+    dark_mode: bool,
+    check_updates: bool,
+}
+
+impl ConfigV3 {
+    /// Carries the settings over to a device config that the first connected device takes, the checkboxes turn
+    /// into the color stages that replaced them. `monitor` is the screen the saved position points at.
+    fn migrate(self, monitor: Option<MonitorIdentity>) -> Config {
+        let mut device = DeviceConfig::new("");
+        device.brightness = self.brightness;
+        device.downscale_method_index = self.downscale_method_index;
+        device.monitor = monitor;
+
+        let mut check = |matches: fn(&StageKind) -> bool, checked: bool| {
+            if let Some(enabled) = pipeline::stage_enabled(&mut device.color_stages, matches) {
+                *enabled = checked;
+            }
+        };
+        check(
+            |stage| matches!(stage, StageKind::FlashLimiter { .. }),
+            self.reduce_bright_effects,
+        );
+        check(
+            |stage| matches!(stage, StageKind::Calibration),
+            self.red_shift_fix,
+        );
+        check(
+            |stage| matches!(stage, StageKind::Overrides(_)),
+            self.highlight_wasd,
+        );
+
+        // The Red Shift Fix was a fixed correction, which the calibration starts from
+        if self.red_shift_fix {
+            device.calibration = Calibration::RED_SHIFT;
+        }
+
+        Config {
+            config_version: CONFIG_VERSION,
+            display_rgb_preview: self.display_rgb_preview,
+            frame_limit: self.frame_limit,
+            interpolation_rate: None,
+            dark_mode: self.dark_mode,
+            check_updates: self.check_updates,
+            devices: vec![device],
+            openrgb: OpenRgbConfig::default(),
+        }
+    }
+}
 
 pub fn read_config() -> Option<Config> {
-    let config_file = File::open(super::paths::config_path().join("config.ron"))
+    let config_text = std::fs::read_to_string(super::paths::config_path().join("config.ron"))
         .log_expect(LogImportance::Error, "Unable to open config file");
-    let config: Config = match from_reader(config_file) {
+
+    if from_str::<ConfigVersion>(&config_text).is_ok_and(|version| version.config_version == 3) {
+        if let Ok(old_config) = from_str::<ConfigV3>(&config_text) {
+            let monitor = xcap::Monitor::all().ok().and_then(|monitors| {
+                monitors
+                    .get(old_config.screen)
+                    .and_then(MonitorIdentity::of)
+            });
+            let config = old_config.migrate(monitor);
+            write_config(&config);
+            logf!(Info, "Migrated config from version 3 to {}", CONFIG_VERSION);
+            return Some(config);
+        }
+    }
+
+    let config: Config = match from_str(&config_text) {
         Ok(x) => x,
         Err(e) => {
             log_this(LogData {
@@ -64,27 +224,15 @@ pub fn read_config() -> Option<Config> {
 pub fn gen_config() {
     let data = Config {
         config_version: CONFIG_VERSION,
-        brightness: 100,
-        reduce_bright_effects: false,
-        screen: 0,
         display_rgb_preview: true,
-        downscale_method_index: 1,
         frame_limit: (60, 15), // (UI, Capture)
-        red_shift_fix: false,
-        highlight_wasd: false,
+        interpolation_rate: None,
         dark_mode: true,
         check_updates: true,
+        devices: Vec::new(),
+        openrgb: OpenRgbConfig::default(),
     };
-
-    let config = PrettyConfig::new()
-        .depth_limit(3)
-        .separate_tuple_members(true)
-        .enumerate_arrays(true);
-
-    let config_str = to_string_pretty(&data, config)
-        .log_expect(LogImportance::Error, "Unable to serialize default config");
-    std::fs::write(paths::config_path().join("config.ron"), config_str)
-        .log_expect(LogImportance::Error, "Unable to write config file");
+    write_config(&data);
 
     log_this(LogData {
         importance: LogImportance::Info,
@@ -102,19 +250,67 @@ pub fn config_exists() -> bool {
     .exists()
 }
 
+/// Takes the saved entry of `model` out of `saved`, or the settings carried over from a version 3 config, or makes a
+/// default entry
+pub fn take_device_config(saved: &mut Vec<DeviceConfig>, model: &str) -> DeviceConfig {
+    let position = saved
+        .iter()
+        .position(|device| device.model == model)
+        .or_else(|| {
+            // Only a real device takes the carried over settings
+            (model != "N/A")
+                .then(|| saved.iter().position(|device| device.model.is_empty()))
+                .flatten()
+        });
+
+    match position {
+        Some(position) => {
+            let mut device_config = saved.remove(position);
+            device_config.model = model.to_string();
+            device_config
+        }
+        None => {
+            logf!(Info, "Adding default config for device {}", model);
+            DeviceConfig::new(model)
+        }
+    }
+}
+
+/// Reorders the device entries so entry `n` belongs to the `n`th connected device, unknown models get a default entry
+pub fn arrange_device_configs(config: &mut Config, models: &[String]) {
+    let mut remaining = std::mem::take(&mut config.devices);
+
+    for model in models {
+        config
+            .devices
+            .push(take_device_config(&mut remaining, model));
+    }
+
+    // Devices which are not connected right now keep their settings for next time
+    config.devices.append(&mut remaining);
+}
+
+/// Changes to device options carry the index of the device they apply to
 pub enum ConfigChange {
     MultipleConfigOptions(Vec<ConfigChange>),
     AllConfigOptions(Config),
-    Brightness(u8),
-    ReduceBrightEffects(bool),
-    Screen(usize),
+    Brightness(usize, u8),
+    Screen(usize, Option<MonitorIdentity>),
+    SpanScreens(usize, bool),
     DisplayRgbPreview(bool),
-    DownscaleMethod(FilterType),
+    DownscaleMethod(usize, DownscaleMethod),
     FrameLimit((u8, u8)),
-    RedShiftFix(bool),
-    HighlightWASD(bool),
+    InterpolationRate(Option<u8>),
+    KeyGeometry(usize, bool),
+    Crop(usize, Option<(f32, f32, f32, f32)>),
+    CaptureWindow(usize, Option<String>),
+    RemoveBlackBars(usize, bool),
+    EdgeSampling(usize, Option<EdgeSampling>),
+    ColorStages(usize, Vec<StageConfig>),
+    Calibration(usize, Calibration),
     Darkmode(bool),
     CheckUpdates(bool),
+    OpenRgb(OpenRgbConfig),
 }
 
 pub fn save_config_option(new: ConfigChange, toasts: &mut Toasts) {
@@ -145,31 +341,51 @@ pub fn save_config_option(new: ConfigChange, toasts: &mut Toasts) {
             }
         }
         ConfigChange::AllConfigOptions(x) => data = x,
-        ConfigChange::Brightness(x) => data.brightness = x,
-        ConfigChange::ReduceBrightEffects(x) => data.reduce_bright_effects = x,
-        ConfigChange::Screen(x) => data.screen = x,
+        ConfigChange::Brightness(i, x) => device_config(&mut data, i).brightness = x,
+        ConfigChange::Screen(i, x) => device_config(&mut data, i).monitor = x,
+        ConfigChange::SpanScreens(i, x) => device_config(&mut data, i).span_screens = x,
         ConfigChange::DisplayRgbPreview(x) => data.display_rgb_preview = x,
-        ConfigChange::DownscaleMethod(x) => {
-            data.downscale_method_index = filter_to_downscale_index(x)
+        ConfigChange::DownscaleMethod(i, x) => {
+            device_config(&mut data, i).downscale_method_index = method_to_downscale_index(x)
         }
         ConfigChange::FrameLimit(x) => data.frame_limit = x,
-        ConfigChange::RedShiftFix(x) => data.red_shift_fix = x,
-        ConfigChange::HighlightWASD(x) => data.highlight_wasd = x,
+        ConfigChange::InterpolationRate(x) => data.interpolation_rate = x,
+        ConfigChange::KeyGeometry(i, x) => device_config(&mut data, i).key_geometry = x,
+        ConfigChange::Crop(i, x) => device_config(&mut data, i).crop = x,
+        ConfigChange::CaptureWindow(i, x) => device_config(&mut data, i).capture_window = x,
+        ConfigChange::RemoveBlackBars(i, x) => device_config(&mut data, i).remove_black_bars = x,
+        ConfigChange::EdgeSampling(i, x) => device_config(&mut data, i).edge_sampling = x,
+        ConfigChange::ColorStages(i, x) => device_config(&mut data, i).color_stages = x,
+        ConfigChange::Calibration(i, x) => device_config(&mut data, i).calibration = x,
         ConfigChange::Darkmode(x) => data.dark_mode = x,
         ConfigChange::CheckUpdates(x) => data.check_updates = x,
+        ConfigChange::OpenRgb(x) => data.openrgb = x,
     }
 
+    write_config(&data);
+}
+
+fn write_config(data: &Config) {
     let config = PrettyConfig::new()
         .depth_limit(3)
         .separate_tuple_members(true)
         .enumerate_arrays(true);
 
-    let config_str = to_string_pretty(&data, config)
+    let config_str = to_string_pretty(data, config)
         .log_expect(LogImportance::Error, "Unable to serialize config");
     std::fs::write(paths::config_path().join("config.ron"), config_str)
         .log_expect(LogImportance::Error, "Unable to write config file");
 }
 
+/// Device entry at `index`, padded with default entries if the config was reset since the devices were arranged
+fn device_config(data: &mut Config, index: usize) -> &mut DeviceConfig {
+    while data.devices.len() <= index {
+        data.devices.push(DeviceConfig::new("N/A"));
+    }
+
+    &mut data.devices[index]
+}
+
 pub fn reset_config() {
     std::fs::remove_file(paths::config_path().join("config.ron"))
         .log_expect(LogImportance::Error, "Unable to delete config file");
@@ -177,13 +393,14 @@ pub fn reset_config() {
     gen_config();
 }
 
-pub fn downscale_index_to_filter(index: u8) -> FilterType {
+pub fn downscale_index_to_method(index: u8) -> DownscaleMethod {
     match index {
-        0 => FilterType::Nearest,
-        1 => FilterType::Triangle,
-        2 => FilterType::CatmullRom,
-        3 => FilterType::Gaussian,
-        4 => FilterType::Lanczos3,
+        0 => DownscaleMethod::Filter(FilterType::Nearest),
+        1 => DownscaleMethod::Filter(FilterType::Triangle),
+        2 => DownscaleMethod::Filter(FilterType::CatmullRom),
+        3 => DownscaleMethod::Filter(FilterType::Gaussian),
+        4 => DownscaleMethod::Filter(FilterType::Lanczos3),
+        5 => DownscaleMethod::Area,
         _ => {
             logf!(
                 Warning,
@@ -191,17 +408,73 @@ pub fn downscale_index_to_filter(index: u8) -> FilterType {
                 index.to_string()
             );
 
-            FilterType::Nearest
+            DownscaleMethod::Filter(FilterType::Nearest)
         }
     }
 }
 
-fn filter_to_downscale_index(filter: FilterType) -> u8 {
-    match filter {
-        FilterType::Nearest => 0,
-        FilterType::Triangle => 1,
-        FilterType::CatmullRom => 2,
-        FilterType::Gaussian => 3,
-        FilterType::Lanczos3 => 4,
+fn method_to_downscale_index(method: DownscaleMethod) -> u8 {
+    match method {
+        DownscaleMethod::Filter(FilterType::Nearest) => 0,
+        DownscaleMethod::Filter(FilterType::Triangle) => 1,
+        DownscaleMethod::Filter(FilterType::CatmullRom) => 2,
+        DownscaleMethod::Filter(FilterType::Gaussian) => 3,
+        DownscaleMethod::Filter(FilterType::Lanczos3) => 4,
+        DownscaleMethod::Area => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_version_3_settings() {
+        let old_config = "(
+    config_version: 3,
+    brightness: 120,
+    reduce_bright_effects: true,
+    screen: 1,
+    display_rgb_preview: false,
+    downscale_method_index: 3,
+    frame_limit: (30, 10),
+    red_shift_fix: true,
+    highlight_wasd: false,
+    dark_mode: false,
+    check_updates: true,
+)";
+        let config = from_str::<ConfigV3>(old_config).unwrap().migrate(None);
+
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert!(!config.display_rgb_preview && !config.dark_mode && config.check_updates);
+        assert_eq!(config.frame_limit, (30, 10));
+
+        let device = &config.devices[0];
+        assert_eq!(device.brightness, 120);
+        assert_eq!(device.downscale_method_index, 3);
+        assert!(device.calibration == Calibration::RED_SHIFT);
+        let enabled = |matches: fn(&StageKind) -> bool| {
+            device
+                .color_stages
+                .iter()
+                .find(|stage| matches(&stage.stage))
+                .unwrap()
+                .enabled
+        };
+        assert!(enabled(|stage| matches!(
+            stage,
+            StageKind::FlashLimiter { .. }
+        )));
+        assert!(enabled(|stage| matches!(stage, StageKind::Calibration)));
+        assert!(!enabled(|stage| matches!(stage, StageKind::Overrides(_))));
+
+        // The carried over settings go to the first real device
+        let mut saved = config.devices.clone();
+        assert_eq!(take_device_config(&mut saved, "N/A").brightness, 100);
+        assert_eq!(
+            take_device_config(&mut saved, "Wooting Two HE").brightness,
+            120
+        );
+        assert!(saved.is_empty());
     }
 }
@@ -0,0 +1,151 @@
+use std::sync::RwLock;
+
+use ron::de::from_str;
+use scorched::{logf, LogData, LogImportance};
+use serde::{Deserialize, Serialize};
+
+use super::{firmware::FirmwareRule, paths};
+
+static BUNDLED_DEFINITIONS: &str = include_str!("../../devices/wooting.ron");
+static DEFINITIONS: RwLock<Vec<DeviceDefinition>> = RwLock::new(Vec::new());
+
+/// Describes a family of devices, see `devices/wooting.ron` for the bundled definitions
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceDefinition {
+    pub name: String,
+    pub models: Vec<String>,
+    pub rgb_size: (u32, u32),
+    pub first_row: u8,
+    #[serde(default)]
+    pub layout: Option<PhysicalLayout>,
+    #[serde(default)]
+    pub led_mask: Vec<(u8, u8)>,
+    #[serde(default)]
+    pub quirks: Vec<Quirk>,
+    /// Firmware compatibility table, versions not covered by any rule are untested
+    #[serde(default)]
+    pub firmware: Vec<FirmwareRule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum PhysicalLayout {
+    SixtyPercent,
+    Tenkeyless,
+    FullSize,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum Quirk {
+    /// The device has no lighting at all
+    NoLighting,
+    /// Leds which are not covered by a keyswitch, color corrections for the switches are skipped on them
+    ExposedLeds(Vec<(u8, u8)>),
+}
+
+impl DeviceDefinition {
+    pub fn has_led(&self, row: u8, column: u8) -> bool {
+        !self.led_mask.contains(&(row, column))
+    }
+
+    pub fn is_exposed(&self, row: u8, column: u8) -> bool {
+        self.quirks.iter().any(|quirk| match quirk {
+            Quirk::ExposedLeds(leds) => leds.contains(&(row, column)),
+            _ => false,
+        })
+    }
+}
+
+/// Loads the user definitions from the config directory followed by the bundled ones, replaces any previously loaded definitions
+pub fn load_definitions() {
+    let mut definitions = Vec::new();
+
+    if let Ok(entries) = std::fs::read_dir(paths::definitions_path()) {
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+            .collect();
+        files.sort();
+
+        for file in files {
+            let parsed = std::fs::read_to_string(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| {
+                    from_str::<Vec<DeviceDefinition>>(&contents).map_err(|e| e.to_string())
+                });
+
+            match parsed {
+                Ok(mut user_definitions) => {
+                    logf!(
+                        Info,
+                        "Loaded {} device definitions from {}",
+                        user_definitions.len(),
+                        file.display()
+                    );
+                    definitions.append(&mut user_definitions);
+                }
+                Err(e) => {
+                    logf!(
+                        Error,
+                        "Unable to read device definitions from {} because of the following error:\n{}",
+                        file.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    match from_str::<Vec<DeviceDefinition>>(BUNDLED_DEFINITIONS) {
+        Ok(mut bundled_definitions) => definitions.append(&mut bundled_definitions),
+        Err(e) => {
+            logf!(Error, "Unable to read bundled device definitions: {}", e);
+        }
+    }
+
+    *DEFINITIONS.write().unwrap() = definitions;
+}
+
+/// Adds a definition with a lower priority than any loaded from files, it is dropped the next time definitions are loaded
+pub fn add_definition(definition: DeviceDefinition) {
+    DEFINITIONS.write().unwrap().push(definition);
+}
+
+pub fn find_definition(model_name: &str) -> Option<DeviceDefinition> {
+    DEFINITIONS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|definition| {
+            definition
+                .models
+                .iter()
+                .any(|pattern| matches_pattern(pattern, model_name))
+        })
+        .cloned()
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of characters
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard in the pattern
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
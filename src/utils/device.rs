@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use scorched::{logf, LogData, LogImportance};
+
+/// Rows and columns of the lighting matrix exposed by the Wooting SDK
+pub const MATRIX_ROWS: usize = 6;
+pub const MATRIX_COLUMNS: usize = 21;
+
+/// A single frame of key colors laid out as `[row][column]`
+pub type KeyFrame = [[(u8, u8, u8); MATRIX_COLUMNS]; MATRIX_ROWS];
+
+/// Frames kept by a virtual device, older ones are dropped so long sessions don't grow without limit
+pub const VIRTUAL_FRAME_LIMIT: usize = 256;
+
+/// Anything that can take a frame of key colors, the capture loop only talks to devices through this
+pub trait RgbDevice: Send {
+    /// Opens the device, returns true when it is ready to receive colors
+    fn connect(&mut self) -> bool;
+    /// Resets the lighting and releases the device
+    fn disconnect(&mut self);
+    /// Model name as reported by the device, "N/A" when nothing is connected
+    fn name(&self) -> String;
+    /// Size of the lighting matrix in (columns, rows)
+    fn rgb_size(&self) -> Option<(u32, u32)>;
+    /// Matrix row lit by the first row of a frame
+    fn first_row(&self) -> u8;
+    /// Sets a single key in the pending frame, nothing is shown until `flush` is called
+    fn set_key(&mut self, row: u8, column: u8, color: (u8, u8, u8));
+    /// Sends the pending frame to the device
+    fn flush(&mut self) -> bool;
+}
+
+/// In-memory keyboard that records the last frames flushed to it, used to run the capture loop without any hardware
+pub struct VirtualDevice {
+    name: String,
+    rgb_size: (u32, u32),
+    connected: bool,
+    pending: KeyFrame,
+    frames: Arc<Mutex<VecDeque<KeyFrame>>>,
+}
+
+impl VirtualDevice {
+    pub fn new(name: &str, rgb_size: (u32, u32)) -> Self {
+        Self {
+            name: name.to_string(),
+            rgb_size,
+            connected: false,
+            pending: [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS],
+            frames: Arc::new(Mutex::new(VecDeque::with_capacity(VIRTUAL_FRAME_LIMIT))),
+        }
+    }
+
+    /// Shared handle to the recorded frames, stays valid after the device is moved into the capture thread
+    pub fn frames(&self) -> Arc<Mutex<VecDeque<KeyFrame>>> {
+        self.frames.clone()
+    }
+}
+
+impl RgbDevice for VirtualDevice {
+    fn connect(&mut self) -> bool {
+        logf!(Info, "Connecting virtual device {}", self.name);
+        self.connected = true;
+        true
+    }
+
+    fn disconnect(&mut self) {
+        logf!(Info, "Disconnecting virtual device {}", self.name);
+        self.pending = [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS];
+        self.connected = false;
+    }
+
+    fn name(&self) -> String {
+        match self.connected {
+            true => self.name.clone(),
+            false => "N/A".to_string(),
+        }
+    }
+
+    fn rgb_size(&self) -> Option<(u32, u32)> {
+        match self.connected {
+            true => Some(self.rgb_size),
+            false => Some((0, 0)),
+        }
+    }
+
+    /// Frames cover the whole matrix, starting on the function row
+    fn first_row(&self) -> u8 {
+        0
+    }
+
+    fn set_key(&mut self, row: u8, column: u8, color: (u8, u8, u8)) {
+        // Out of range keys are ignored the same way the SDK ignores them
+        if let Some(key) = self
+            .pending
+            .get_mut(row as usize)
+            .and_then(|row| row.get_mut(column as usize))
+        {
+            *key = color;
+        }
+    }
+
+    fn flush(&mut self) -> bool {
+        if !self.connected {
+            return false;
+        }
+
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() == VIRTUAL_FRAME_LIMIT {
+            frames.pop_front();
+        }
+        frames.push_back(self.pending);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::utils::{
+        calibration::Calibration,
+        pipeline::{ColorPipeline, StageConfig, StageKind},
+        wooting,
+    };
+
+    #[test]
+    fn draws_image_into_virtual_device() {
+        let mut device = VirtualDevice::new("Virtual Keyboard", (3, 2));
+        assert!(device.connect());
+
+        let image = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 100, y as u8 * 200, 50, 255]));
+        let stages = [StageConfig {
+            stage: StageKind::Brightness,
+            enabled: true,
+        }];
+        let mut pipeline = ColorPipeline::new(&stages, 100, Calibration::default());
+
+        let model_name = device.name();
+        wooting::draw_rgb(
+            &mut device,
+            &DynamicImage::ImageRgba8(image),
+            &mut pipeline,
+            model_name,
+        );
+
+        let frames = device.frames();
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+
+        let frame = frames.back().unwrap();
+        assert_eq!(frame[0][0], (0, 0, 50));
+        assert_eq!(frame[0][2], (200, 0, 50));
+        assert_eq!(frame[1][1], (100, 200, 50));
+        assert_eq!(frame[1][3], (0, 0, 0));
+        assert_eq!(frame[2], [(0, 0, 0); MATRIX_COLUMNS]);
+    }
+
+    #[test]
+    fn keeps_only_the_latest_frames() {
+        let mut device = VirtualDevice::new("Virtual Keyboard", (1, 1));
+        device.connect();
+
+        for value in 0..VIRTUAL_FRAME_LIMIT + 10 {
+            device.set_key(0, 0, (value as u8, 0, 0));
+            device.flush();
+        }
+
+        let frames = device.frames();
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), VIRTUAL_FRAME_LIMIT);
+        assert_eq!(
+            frames.back().unwrap()[0][0],
+            ((VIRTUAL_FRAME_LIMIT + 9) as u8, 0, 0)
+        );
+    }
+}
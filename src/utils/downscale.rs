@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use scorched::{LogExpect, LogImportance};
+
+use super::layout::KeyLayout;
+
+/// How a capture is reduced to the size of the lighting
+#[derive(Clone, Copy, PartialEq)]
+pub enum DownscaleMethod {
+    /// Resizes the captured area with one of the filters of the image crate
+    Filter(FilterType),
+    /// Averages the part of the captured area under each key straight from the capture buffer
+    Area,
+}
+
+/// Part of an image in pixels as (x, y, width, height)
+pub type Region = (u32, u32, u32, u32);
+
+/// Area average downscaler that keeps its output image between frames
+pub struct AreaDownscaler {
+    output: DynamicImage,
+}
+
+impl Default for AreaDownscaler {
+    fn default() -> Self {
+        Self {
+            output: DynamicImage::new_rgba8(0, 0),
+        }
+    }
+}
+
+impl AreaDownscaler {
+    /// Averages `region` of `img` down to `size`, nothing is copied unless the capture is not RGBA.
+    ///
+    /// With a `layout` and the matrix row of its first downscaled row, each key is averaged over the area it covers
+    /// on the board, the same way `layout::sample_layout` does.
+    pub fn downscale(
+        &mut self,
+        img: &DynamicImage,
+        region: Region,
+        size: (u32, u32),
+        layout: Option<(&KeyLayout, u8)>,
+    ) -> &DynamicImage {
+        let raw = match img.as_rgba8() {
+            Some(raw) => Cow::Borrowed(raw),
+            None => Cow::Owned(img.to_rgba8()),
+        };
+        if self.output.dimensions() != size {
+            self.output = DynamicImage::new_rgba8(size.0, size.1);
+        }
+        let output = self
+            .output
+            .as_mut_rgba8()
+            .log_expect(LogImportance::Error, "Area downscaler output is not RGBA");
+
+        let (x, y, width, height) = region;
+        for (column, row, pixel) in output.enumerate_pixels_mut() {
+            let x0 = x + column * width / size.0;
+            let y0 = y + row * height / size.1;
+            let x1 = (x + (column + 1) * width / size.0).max(x0 + 1);
+            let y1 = (y + (row + 1) * height / size.1).max(y0 + 1);
+            *pixel = average(&raw, (x0, y0, x1, y1));
+        }
+
+        if let Some((layout, first_row)) = layout {
+            let (layout_width, layout_height) = layout.size();
+            let scale_x = width as f32 / layout_width;
+            let scale_y = height as f32 / layout_height;
+
+            for key in layout.keys.iter() {
+                let Some(row) = key.row.checked_sub(first_row) else {
+                    continue;
+                };
+                if key.column as u32 >= size.0 || row as u32 >= size.1 {
+                    continue;
+                }
+
+                let x0 = x + ((key.x * scale_x) as u32).min(width - 1);
+                let y0 = y + ((key.y * scale_y) as u32).min(height - 1);
+                let x1 =
+                    (x + ((key.x + key.width) * scale_x).ceil() as u32).clamp(x0 + 1, x + width);
+                let y1 =
+                    (y + ((key.y + key.height) * scale_y).ceil() as u32).clamp(y0 + 1, y + height);
+                output.put_pixel(
+                    key.column as u32,
+                    row as u32,
+                    average(&raw, (x0, y0, x1, y1)),
+                );
+            }
+        }
+
+        &self.output
+    }
+}
+
+/// Average color of every pixel from (x0, y0) up to but not including (x1, y1)
+pub fn average(raw: &RgbaImage, (x0, y0, x1, y1): (u32, u32, u32, u32)) -> Rgba<u8> {
+    let stride = raw.width() as usize * 4;
+    let data = raw.as_raw();
+
+    let mut sum = [0u64; 3];
+    for y in y0..y1 {
+        let line = &data[y as usize * stride..][x0 as usize * 4..x1 as usize * 4];
+        for pixel in line.chunks_exact(4) {
+            sum[0] += pixel[0] as u64;
+            sum[1] += pixel[1] as u64;
+            sum[2] += pixel[2] as u64;
+        }
+    }
+
+    let count = ((x1 - x0) as u64 * (y1 - y0) as u64).max(1);
+    let [r, g, b] = sum.map(|channel| ((channel + count / 2) / count) as u8);
+    Rgba([r, g, b, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn average_matches_the_mean_of_the_area() {
+        let raw = RgbaImage::from_fn(4, 3, |x, y| {
+            Rgba([(x * 10 + y) as u8, 200, (x * y) as u8, 0])
+        });
+
+        // Columns 1 and 2 of rows 1 and 2: red 11, 21, 12, 22, blue 1, 2, 2, 4
+        assert_eq!(average(&raw, (1, 1, 3, 3)), Rgba([17, 200, 2, 255]));
+    }
+
+    #[test]
+    fn average_uses_every_pixel_of_large_areas() {
+        // Stripes one pixel wide, sampling every other column would see only one of the colors
+        let raw = RgbaImage::from_fn(200, 100, |x, _| match x % 2 {
+            0 => Rgba([0, 0, 0, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+
+        assert_eq!(average(&raw, (0, 0, 200, 100)), Rgba([128, 128, 128, 255]));
+        assert_eq!(average(&raw, (0, 0, 130, 100)), Rgba([128, 128, 128, 255]));
+    }
+}
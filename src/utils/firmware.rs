@@ -0,0 +1,133 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::definitions::find_definition;
+
+/// Firmware version of a device, compares the same way as a semver version
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl TryFrom<String> for FirmwareVersion {
+    type Error = String;
+
+    /// Parses a "major.minor.patch" version, a leading "v" is allowed
+    fn try_from(version: String) -> Result<Self, Self::Error> {
+        let parts: Vec<u8> = version
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid firmware version {}: {}", version, e))?;
+
+        match parts[..] {
+            [major, minor, patch] => Ok(Self {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(format!(
+                "Invalid firmware version {}: expected major.minor.patch",
+                version
+            )),
+        }
+    }
+}
+
+impl From<FirmwareVersion> for String {
+    fn from(version: FirmwareVersion) -> Self {
+        version.to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FirmwareStatus {
+    KnownGood,
+    KnownBroken,
+    Untested,
+}
+
+/// Parts of the app that depend on the firmware, listed in a rule when the firmware lacks them
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FirmwareFeature {
+    /// Controlling the lighting through the SDK
+    Lighting,
+    /// Reading the serial and manufacture date from the device
+    SerialReport,
+}
+
+/// One entry of the compatibility table of a device definition, covering the versions from `from` to `to` inclusive
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FirmwareRule {
+    #[serde(default)]
+    pub from: Option<FirmwareVersion>,
+    #[serde(default)]
+    pub to: Option<FirmwareVersion>,
+    pub status: FirmwareStatus,
+    #[serde(default)]
+    pub missing_features: Vec<FirmwareFeature>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+impl FirmwareRule {
+    pub fn matches(&self, version: FirmwareVersion) -> bool {
+        self.from.is_none_or(|from| version >= from) && self.to.is_none_or(|to| version <= to)
+    }
+}
+
+/// How well the running firmware of a device is supported
+#[derive(Clone, PartialEq, Debug)]
+pub struct FirmwareCompatibility {
+    pub status: FirmwareStatus,
+    pub missing_features: Vec<FirmwareFeature>,
+    pub note: Option<String>,
+}
+
+impl FirmwareCompatibility {
+    pub fn supports(&self, feature: FirmwareFeature) -> bool {
+        !self.missing_features.contains(&feature)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self.status {
+            FirmwareStatus::KnownGood => "Supported",
+            FirmwareStatus::KnownBroken => "Unsupported",
+            FirmwareStatus::Untested => "Untested",
+        }
+    }
+}
+
+/// Looks the firmware up in the compatibility table of the model's definition, the first matching rule is used and
+/// versions without a rule are untested
+pub fn check_compatibility(model_name: &str, version: FirmwareVersion) -> FirmwareCompatibility {
+    find_definition(model_name)
+        .and_then(|definition| {
+            definition
+                .firmware
+                .into_iter()
+                .find(|rule| rule.matches(version))
+        })
+        .map(|rule| FirmwareCompatibility {
+            status: rule.status,
+            missing_features: rule.missing_features,
+            note: rule.note,
+        })
+        .unwrap_or(FirmwareCompatibility {
+            status: FirmwareStatus::Untested,
+            missing_features: Vec::new(),
+            note: None,
+        })
+}
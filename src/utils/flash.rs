@@ -0,0 +1,236 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::{
+    oklab,
+    pipeline::{ColorStage, Key, StageKind},
+};
+
+/// Flashes are counted over this long, the guidelines limit flashes in any one second
+const WINDOW: Duration = Duration::from_secs(1);
+/// A change only counts towards a flash while the darker side is below this relative luminance, as in WCAG 2.3.1
+const DARK_LIMIT: f32 = 0.8;
+/// Seconds the brightness takes to get about two thirds of the way down when flashing starts and back up after
+const RAMP_DOWN: f32 = 0.08;
+const RAMP_UP: f32 = 1.0;
+/// Part of `threshold` the limited swing is held to, leaving room for the ramp
+const HEADROOM: f32 = 0.8;
+
+/// Keeps the lighting from flashing more often than `flashes_per_second`.
+///
+/// A flash is a rise and fall of the average relative luminance of the keys by at least `threshold`, counted over a
+/// sliding window of a second. The brightness is ramped down once the flashes are one short of the limit, until the
+/// swing in that window stays under `threshold`, and ramped back up once the flashing stops. When a single turn of the
+/// luminance would reach the limit it is cut down at once, the frames before that turn already show the full swing.
+pub struct FlashLimiter {
+    pub flashes_per_second: f32,
+    pub threshold: f32,
+    /// Luminance of every frame in the window
+    history: VecDeque<(Instant, f32)>,
+    /// Changes of direction by at least `threshold` in the window, two make a flash
+    transitions: VecDeque<Instant>,
+    /// Luminance the current rise or fall started from and whether it is rising
+    extreme: Option<(f32, bool)>,
+    gain: f32,
+    target_gain: f32,
+    last_update: Option<Instant>,
+}
+
+impl FlashLimiter {
+    pub fn new(flashes_per_second: f32, threshold: f32) -> Self {
+        Self {
+            flashes_per_second,
+            threshold,
+            history: VecDeque::new(),
+            transitions: VecDeque::new(),
+            extreme: None,
+            gain: 1.0,
+            target_gain: 1.0,
+            last_update: None,
+        }
+    }
+
+    /// Limits a frame shown at `now`, separate from `apply` so recorded or synthetic sequences can be fed at any pace
+    pub fn update(&mut self, keys: &mut [Key], now: Instant) {
+        let luminance = average_luminance(keys);
+        self.track(luminance, now);
+
+        // Two transitions make a flash
+        let transitions = self.transitions.len() as f32;
+        let allowed = self.flashes_per_second * 2.0;
+        self.target_gain = match transitions + 2.0 >= allowed {
+            true => {
+                let (darkest, brightest) = self.history.iter().fold(
+                    (f32::MAX, f32::MIN),
+                    |(darkest, brightest), (_, luminance)| {
+                        (darkest.min(*luminance), brightest.max(*luminance))
+                    },
+                );
+                (self.threshold * HEADROOM / (brightest - darkest).max(f32::EPSILON)).min(1.0)
+            }
+            false => 1.0,
+        };
+
+        let elapsed = self
+            .last_update
+            .map(|last_update| now.saturating_duration_since(last_update).as_secs_f32())
+            .unwrap_or(0.0);
+        self.last_update = Some(now);
+        let ramp = match self.target_gain < self.gain {
+            true => RAMP_DOWN,
+            false => RAMP_UP,
+        };
+        self.gain += (self.target_gain - self.gain) * (1.0 - (-elapsed / ramp).exp());
+        if transitions + 1.0 >= allowed {
+            self.gain = self.gain.min(self.target_gain);
+        }
+
+        // The gain is in linear light, the keys are gamma encoded
+        let scale = self.gain.powf(1.0 / 2.2);
+        for key in keys {
+            key.color = key.color.map(|channel| channel * scale);
+        }
+    }
+
+    /// Adds a frame to the window and counts a transition when the luminance turns around by at least `threshold`
+    fn track(&mut self, luminance: f32, now: Instant) {
+        while self
+            .history
+            .front()
+            .is_some_and(|(time, _)| now.saturating_duration_since(*time) > WINDOW)
+        {
+            self.history.pop_front();
+        }
+        while self
+            .transitions
+            .front()
+            .is_some_and(|time| now.saturating_duration_since(*time) > WINDOW)
+        {
+            self.transitions.pop_front();
+        }
+        self.history.push_back((now, luminance));
+
+        let Some((extreme, rising)) = self.extreme else {
+            self.extreme = Some((luminance, true));
+            return;
+        };
+
+        // Following the current direction moves the extreme along, turning around far enough counts a transition
+        let turned = match rising {
+            true => extreme - luminance,
+            false => luminance - extreme,
+        };
+        if turned >= self.threshold && extreme.min(luminance) < DARK_LIMIT {
+            self.transitions.push_back(now);
+            self.extreme = Some((luminance, !rising));
+        } else if turned < 0.0 {
+            self.extreme = Some((luminance, rising));
+        }
+    }
+}
+
+impl ColorStage for FlashLimiter {
+    fn apply(&mut self, keys: &mut [Key]) {
+        self.update(keys, Instant::now());
+    }
+
+    fn settled(&self) -> bool {
+        (self.target_gain - self.gain).abs() < 0.01
+    }
+
+    fn reconfigure(&mut self, stage: &StageKind) -> bool {
+        match stage {
+            StageKind::FlashLimiter {
+                flashes_per_second,
+                threshold,
+            } => {
+                self.flashes_per_second = *flashes_per_second;
+                self.threshold = *threshold;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Average relative luminance of the keys from 0 to 1
+fn average_luminance(keys: &[Key]) -> f32 {
+    let total: f32 = keys
+        .iter()
+        .map(|key| {
+            let [r, g, b] = key.color.map(|channel| oklab::to_linear(channel / 255.0));
+            0.2126 * r + 0.7152 * g + 0.0722 * b
+        })
+        .sum();
+
+    total / keys.len().max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_RATE: f32 = 60.0;
+
+    fn gray(level: f32) -> Vec<Key> {
+        (0..4)
+            .map(|column| Key {
+                row: 0,
+                column,
+                color: [level; 3],
+                exposed: false,
+            })
+            .collect()
+    }
+
+    /// Feeds a frame of `level(seconds)` gray every 60th of a second for `seconds`, returns the frames that came out
+    /// and when they were shown
+    fn run(
+        limiter: &mut FlashLimiter,
+        seconds: f32,
+        level: impl Fn(f32) -> f32,
+    ) -> Vec<(Instant, Vec<Key>)> {
+        let start = Instant::now();
+        (0..(seconds * FRAME_RATE) as u32)
+            .map(|frame| {
+                let time = frame as f32 / FRAME_RATE;
+                let now = start + Duration::from_secs_f32(time);
+                let mut keys = gray(level(time));
+                limiter.update(&mut keys, now);
+                (now, keys)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn holds_a_strobe_under_the_limit() {
+        let mut limiter = FlashLimiter::new(3.0, 0.1);
+        // Five full swings from black to white a second
+        let output = run(&mut limiter, 3.0, |time| match (time * 10.0) as u32 % 2 {
+            0 => 255.0,
+            _ => 0.0,
+        });
+
+        // The output is counted the same way the limiter counts its input
+        let mut counter = FlashLimiter::new(3.0, 0.1);
+        for (now, keys) in output {
+            counter.track(average_luminance(&keys), now);
+            let flashes = counter.transitions.len() as f32 / 2.0;
+            assert!(flashes < 3.0, "{} flashes in a second", flashes);
+        }
+    }
+
+    #[test]
+    fn passes_a_slow_fade_through() {
+        let mut limiter = FlashLimiter::new(3.0, 0.1);
+        // Black to white and back over four seconds
+        let level = |time: f32| (1.0 - (time / 2.0 - 1.0).abs()) * 255.0;
+        let output = run(&mut limiter, 4.0, level);
+
+        for (frame, (_, keys)) in output.iter().enumerate() {
+            assert!(keys == &gray(level(frame as f32 / FRAME_RATE)));
+        }
+    }
+}
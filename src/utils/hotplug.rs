@@ -0,0 +1,96 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use scorched::{logf, LogData, LogImportance};
+
+use super::wooting::{self, DeviceInfo};
+
+/// How often the plugged in devices are listed, listing them does not touch the SDK connection
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+static SUBSCRIBERS: Mutex<Vec<Sender<DeviceEvent>>> = Mutex::new(Vec::new());
+static DEVICE_LOST: AtomicBool = AtomicBool::new(false);
+static WATCHER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Changes to the connected devices, a device is identified by the serial in its info because the SDK index of the
+/// other devices can change with it. The cached device info is already up to date when an event is published.
+#[derive(Clone)]
+pub enum DeviceEvent {
+    Connected(DeviceInfo),
+    Disconnected(DeviceInfo),
+}
+
+/// Returns a channel that receives every device event published after this call
+pub fn subscribe() -> Receiver<DeviceEvent> {
+    let (sender, receiver) = channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+fn publish(event: DeviceEvent) {
+    // Subscribers that dropped their receiver are removed
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|sender| sender.send(event.clone()).is_ok());
+}
+
+unsafe extern "C" fn on_device_lost() {
+    DEVICE_LOST.store(true, Ordering::Relaxed);
+}
+
+/// Starts the thread that watches for devices being plugged in or removed, only the first call does anything
+pub fn start_watcher() {
+    if WATCHER_STARTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    wooting::set_disconnected_callback(on_device_lost);
+
+    std::thread::spawn(|| {
+        let mut paths = wooting::device_paths();
+
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+
+            // The SDK drops every connection when writing to one device fails, so it is reconnected even if the
+            // same devices are still plugged in
+            let lost = DEVICE_LOST.swap(false, Ordering::Relaxed);
+            let current = wooting::device_paths();
+            if !lost && current == paths {
+                continue;
+            }
+            paths = current;
+
+            // The cached info is refreshed before anyone hears about the change
+            let old_info = wooting::device_info();
+            let info = wooting::update_device_info();
+            publish_changes(&old_info, &info);
+        }
+    });
+}
+
+fn publish_changes(old_info: &[DeviceInfo], info: &[DeviceInfo]) {
+    // Devices without a serial can not be followed, they are treated as removed and plugged in again
+    for device in old_info
+        .iter()
+        .filter(|device| !wooting::is_known(info, device))
+    {
+        logf!(Warning, "Device disconnected: {}", device.model);
+        publish(DeviceEvent::Disconnected(device.clone()));
+    }
+
+    for device in info
+        .iter()
+        .filter(|device| !wooting::is_known(old_info, device))
+    {
+        logf!(Info, "Device connected: {}", device.model);
+        publish(DeviceEvent::Connected(device.clone()));
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, GenericImageView};
+
+/// Blends from the frame a device was showing to its newest frame, so the output can be drawn more often than the
+/// screen is captured
+pub struct Interpolator {
+    previous: Option<DynamicImage>,
+    newest: Option<DynamicImage>,
+    output: DynamicImage,
+    started: Instant,
+}
+
+impl Default for Interpolator {
+    fn default() -> Self {
+        Self {
+            previous: None,
+            newest: None,
+            output: DynamicImage::new_rgba8(0, 0),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Interpolator {
+    /// Starts blending towards `frame` from whatever was blended last, a change of size skips the blend
+    pub fn push(&mut self, frame: &DynamicImage) {
+        self.previous = match self.newest.take() {
+            Some(_) if self.output.dimensions() == frame.dimensions() => Some(self.output.clone()),
+            _ => None,
+        };
+        self.newest = Some(frame.clone());
+        self.started = Instant::now();
+    }
+
+    /// The blend at this moment, reaching the newest frame once `duration` has passed since it was pushed
+    pub fn frame(&mut self, duration: Duration) -> Option<&DynamicImage> {
+        let newest = self.newest.as_ref()?;
+        let progress = match duration.is_zero() {
+            true => 1.0,
+            false => (self.started.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0),
+        };
+
+        self.output = match (self.previous.as_ref(), newest.as_rgba8()) {
+            (Some(previous), Some(newest_raw)) if progress < 1.0 => {
+                let mut output = newest_raw.clone();
+                for (pixel, from) in output.pixels_mut().zip(previous.to_rgba8().pixels()) {
+                    for (channel, from) in pixel.0.iter_mut().zip(from.0) {
+                        *channel = (from as f32 + (*channel as f32 - from as f32) * progress)
+                            .round() as u8;
+                    }
+                }
+                DynamicImage::ImageRgba8(output)
+            }
+            _ => newest.clone(),
+        };
+
+        Some(&self.output)
+    }
+}
@@ -0,0 +1,309 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba};
+
+use super::definitions::{find_definition, PhysicalLayout};
+
+/// Resolution the screen is reduced to before sampling, in pixels per keyboard unit
+const SAMPLES_PER_UNIT: f32 = 8.0;
+
+/// A key on the physical board, positions and sizes are in keyboard units where 1u is a standard key
+#[derive(Clone, Copy)]
+pub struct Key {
+    pub row: u8,
+    pub column: u8,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Clone)]
+pub struct KeyLayout {
+    pub keys: Vec<Key>,
+}
+
+impl KeyLayout {
+    /// Width and height of the board in keyboard units
+    pub fn size(&self) -> (f32, f32) {
+        self.keys.iter().fold((0.0, 0.0), |(width, height), key| {
+            (width.max(key.x + key.width), height.max(key.y + key.height))
+        })
+    }
+
+    /// Keeps only the keys lit by a frame of `rgb_size` starting on matrix row `first_row`, moved so the remaining
+    /// keys start at the top left. Keys the device does not light would otherwise take up part of the screen.
+    pub fn clip(&self, rgb_size: (u32, u32), first_row: u8) -> KeyLayout {
+        let keys: Vec<Key> = self
+            .keys
+            .iter()
+            .filter(|key| {
+                (key.column as u32) < rgb_size.0
+                    && key
+                        .row
+                        .checked_sub(first_row)
+                        .is_some_and(|row| (row as u32) < rgb_size.1)
+            })
+            .copied()
+            .collect();
+
+        let (left, top) = keys.iter().fold((f32::MAX, f32::MAX), |(left, top), key| {
+            (left.min(key.x), top.min(key.y))
+        });
+
+        KeyLayout {
+            keys: keys
+                .into_iter()
+                .map(|key| Key {
+                    x: key.x - left,
+                    y: key.y - top,
+                    ..key
+                })
+                .collect(),
+        }
+    }
+
+    /// Adds a row of 1u high keys, each given as (column, x, width)
+    fn add_row(&mut self, row: u8, y: f32, keys: &[(u8, f32, f32)]) {
+        for &(column, x, width) in keys {
+            self.keys.push(Key {
+                row,
+                column,
+                x,
+                y,
+                width,
+                height: 1.0,
+            });
+        }
+    }
+
+    /// Adds a run of 1u keys on consecutive columns starting at `column` and `x`
+    fn add_run(&mut self, row: u8, y: f32, column: u8, x: f32, count: u8) {
+        for i in 0..count {
+            self.add_row(row, y, &[(column + i, x + i as f32, 1.0)]);
+        }
+    }
+
+    /// Spacebar LEDs on columns 4 to 9, splitting the 6.25u bar evenly between them
+    fn add_spacebar(&mut self, row: u8, y: f32) {
+        let width = 6.25 / 6.0;
+        for i in 0..6 {
+            self.add_row(row, y, &[(4 + i, 3.75 + i as f32 * width, width)]);
+        }
+    }
+
+    /// Rows one to five of the matrix, the alphanumeric block shared by every Wooting keyboard
+    fn add_main_block(&mut self, y: f32) {
+        self.add_run(1, y, 0, 0.0, 13);
+        self.add_row(1, y, &[(13, 13.0, 2.0)]);
+
+        self.add_row(2, y + 1.0, &[(0, 0.0, 1.5)]);
+        self.add_run(2, y + 1.0, 1, 1.5, 12);
+        self.add_row(2, y + 1.0, &[(13, 13.5, 1.5)]);
+
+        self.add_row(3, y + 2.0, &[(0, 0.0, 1.75)]);
+        self.add_run(3, y + 2.0, 1, 1.75, 11);
+        self.add_row(3, y + 2.0, &[(13, 12.75, 2.25)]);
+
+        self.add_row(4, y + 3.0, &[(0, 0.0, 2.25)]);
+        self.add_run(4, y + 3.0, 2, 2.25, 10);
+        self.add_row(4, y + 3.0, &[(13, 12.25, 2.75)]);
+
+        self.add_row(
+            5,
+            y + 4.0,
+            &[
+                (0, 0.0, 1.25),
+                (1, 1.25, 1.25),
+                (2, 2.5, 1.25),
+                (10, 10.0, 1.25),
+                (11, 11.25, 1.25),
+                (12, 12.5, 1.25),
+                (13, 13.75, 1.25),
+            ],
+        );
+        self.add_spacebar(5, y + 4.0);
+    }
+
+    /// Function row and navigation cluster of the tenkeyless and full size boards
+    fn add_tenkeyless_block(&mut self) {
+        self.add_row(0, 0.0, &[(0, 0.0, 1.0)]);
+        self.add_run(0, 0.0, 2, 2.0, 4);
+        self.add_run(0, 0.0, 6, 6.5, 4);
+        self.add_run(0, 0.0, 10, 11.0, 4);
+        self.add_run(0, 0.0, 14, 15.25, 3);
+
+        self.add_main_block(1.25);
+
+        self.add_run(1, 1.25, 14, 15.25, 3);
+        self.add_run(2, 2.25, 14, 15.25, 3);
+        self.add_row(4, 4.25, &[(15, 16.25, 1.0)]);
+        self.add_run(5, 5.25, 14, 15.25, 3);
+    }
+}
+
+pub fn sixty_percent() -> KeyLayout {
+    let mut layout = KeyLayout { keys: Vec::new() };
+    layout.add_main_block(0.0);
+    layout
+}
+
+pub fn tenkeyless() -> KeyLayout {
+    let mut layout = KeyLayout { keys: Vec::new() };
+    layout.add_tenkeyless_block();
+    layout
+}
+
+pub fn full_size() -> KeyLayout {
+    let mut layout = KeyLayout { keys: Vec::new() };
+    layout.add_tenkeyless_block();
+
+    // Analog profile keys above the numpad and the numpad itself
+    layout.add_run(0, 0.0, 17, 18.5, 4);
+    layout.add_run(1, 1.25, 17, 18.5, 4);
+    layout.add_run(2, 2.25, 17, 18.5, 3);
+    layout.keys.push(Key {
+        row: 2,
+        column: 20,
+        x: 21.5,
+        y: 2.25,
+        width: 1.0,
+        height: 2.0,
+    });
+    layout.add_run(3, 3.25, 17, 18.5, 3);
+    layout.add_run(4, 4.25, 17, 18.5, 3);
+    layout.keys.push(Key {
+        row: 4,
+        column: 20,
+        x: 21.5,
+        y: 4.25,
+        width: 1.0,
+        height: 2.0,
+    });
+    layout.add_row(5, 5.25, &[(18, 18.5, 2.0), (19, 20.5, 1.0)]);
+
+    layout
+}
+
+pub fn get_layout(model_name: &str) -> Option<KeyLayout> {
+    match find_definition(model_name)?.layout? {
+        PhysicalLayout::SixtyPercent => Some(sixty_percent()),
+        PhysicalLayout::Tenkeyless => Some(tenkeyless()),
+        PhysicalLayout::FullSize => Some(full_size()),
+    }
+}
+
+/// Every key lit by a frame of `rgb_size` starting on matrix row `first_row`. Keys come from the layout of the
+/// definition, or a grid of 1u keys when it has none, leaving out the positions in its led mask.
+pub fn lit_keys(model_name: &str, rgb_size: (u32, u32), first_row: u8) -> KeyLayout {
+    let definition = find_definition(model_name);
+    let layout = match definition.as_ref().and_then(|definition| definition.layout) {
+        Some(PhysicalLayout::SixtyPercent) => sixty_percent().clip(rgb_size, first_row),
+        Some(PhysicalLayout::Tenkeyless) => tenkeyless().clip(rgb_size, first_row),
+        Some(PhysicalLayout::FullSize) => full_size().clip(rgb_size, first_row),
+        None => {
+            let mut layout = KeyLayout { keys: Vec::new() };
+            for row in 0..rgb_size.1 {
+                let matrix_row = row as u8 + first_row;
+                layout.add_run(matrix_row, row as f32, 0, 0.0, rgb_size.0 as u8);
+            }
+            layout
+        }
+    };
+
+    KeyLayout {
+        keys: layout
+            .keys
+            .into_iter()
+            .filter(|key| {
+                definition
+                    .as_ref()
+                    .is_none_or(|definition| definition.has_led(key.row, key.column))
+            })
+            .collect(),
+    }
+}
+
+/// Samples the area of the screen under every key of `layout`, the screen is stretched over the whole board.
+/// The result is laid out like a uniformly downscaled frame where pixel (x, y) lights matrix key (y + first_row, x),
+/// pixels without a key under them keep the uniformly downscaled color.
+pub fn sample_layout(
+    img: &DynamicImage,
+    layout: &KeyLayout,
+    rgb_size: (u32, u32),
+    first_row: u8,
+    downscale_method: FilterType,
+) -> DynamicImage {
+    let mut frame = img
+        .resize_exact(rgb_size.0, rgb_size.1, downscale_method)
+        .to_rgba8();
+
+    let (layout_width, layout_height) = layout.size();
+    let sample = img.resize_exact(
+        (layout_width * SAMPLES_PER_UNIT).ceil() as u32,
+        (layout_height * SAMPLES_PER_UNIT).ceil() as u32,
+        downscale_method,
+    );
+
+    for key in layout.keys.iter() {
+        let Some(y) = key.row.checked_sub(first_row) else {
+            continue;
+        };
+        if key.column as u32 >= rgb_size.0 || y as u32 >= rgb_size.1 {
+            continue;
+        }
+
+        let x0 = (key.x * SAMPLES_PER_UNIT).floor() as u32;
+        let y0 = (key.y * SAMPLES_PER_UNIT).floor() as u32;
+        let x1 = (((key.x + key.width) * SAMPLES_PER_UNIT).ceil() as u32).min(sample.width());
+        let y1 = (((key.y + key.height) * SAMPLES_PER_UNIT).ceil() as u32).min(sample.height());
+
+        let mut sum = [0u32; 3];
+        let mut count = 0;
+        for sample_y in y0..y1 {
+            for sample_x in x0..x1 {
+                let Rgba([r, g, b, _]) = sample.get_pixel(sample_x, sample_y);
+                sum[0] += r as u32;
+                sum[1] += g as u32;
+                sum[2] += b as u32;
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            continue;
+        }
+
+        frame.put_pixel(
+            key.column as u32,
+            y as u32,
+            Rgba([
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+                255,
+            ]),
+        );
+    }
+
+    DynamicImage::ImageRgba8(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_drops_keys_the_device_does_not_light() {
+        let layout = full_size().clip((17, 6), 0);
+
+        assert!(layout.keys.iter().all(|key| key.column < 17));
+        assert_eq!(layout.size(), (18.25, 6.25));
+
+        // 60% frames start on the number row, which moves to the top of the screen
+        let layout = full_size().clip((14, 5), 1);
+        assert!(layout
+            .keys
+            .iter()
+            .all(|key| key.row >= 1 && key.column < 14));
+        assert_eq!(layout.size(), (15.0, 5.0));
+    }
+}
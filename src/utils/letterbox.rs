@@ -0,0 +1,192 @@
+use image::{GenericImageView, Rgba};
+
+/// Brightest a pixel in a black bar can be, bars are rarely pure black after video compression
+const BLACK_THRESHOLD: u8 = 24;
+/// Pixels checked along each row or column
+const SAMPLES: u32 = 32;
+/// Frames a new border has to be measured for before it is used, about a second at the default frame limit
+const STABLE_FRAMES: u32 = 15;
+/// Measured borders within this many pixels of each other count as the same
+const TOLERANCE: u32 = 4;
+/// Bars thinner than this part of the image are left alone
+const MIN_BAR: f32 = 0.02;
+/// Largest part of each side that can be a bar
+const MAX_BAR: f32 = 0.35;
+
+/// Size of the black bars in pixels, bars always come in pairs so each value applies to both sides
+#[derive(Clone, Copy, PartialEq, Default)]
+struct Borders {
+    vertical: u32,
+    horizontal: u32,
+}
+
+impl Borders {
+    fn close_to(&self, other: &Borders) -> bool {
+        self.vertical.abs_diff(other.vertical) <= TOLERANCE
+            && self.horizontal.abs_diff(other.horizontal) <= TOLERANCE
+    }
+}
+
+/// Finds black bars that stay in place over several frames.
+///
+/// A new border is only used once it has been measured for `STABLE_FRAMES` frames in a row and frames that are dark
+/// on both sides are ignored, so dark scenes and fades do not make the crop jump around.
+#[derive(Default)]
+pub struct LetterboxDetector {
+    size: (u32, u32),
+    current: Borders,
+    candidate: Borders,
+    candidate_frames: u32,
+}
+
+impl LetterboxDetector {
+    /// Measures the bars of `img` and returns the part inside of them as (x, y, width, height) in pixels
+    pub fn update<I: GenericImageView<Pixel = Rgba<u8>>>(
+        &mut self,
+        img: &I,
+    ) -> (u32, u32, u32, u32) {
+        if img.dimensions() != self.size {
+            *self = Self::default();
+            self.size = img.dimensions();
+        }
+
+        if let Some(measured) = measure(img) {
+            if measured.close_to(&self.current) {
+                self.candidate_frames = 0;
+            } else if measured.close_to(&self.candidate) {
+                self.candidate_frames += 1;
+                if self.candidate_frames >= STABLE_FRAMES {
+                    self.current = self.candidate;
+                    self.candidate_frames = 0;
+                }
+            } else {
+                self.candidate = measured;
+                self.candidate_frames = 1;
+            }
+        }
+
+        let Borders {
+            vertical,
+            horizontal,
+        } = self.current;
+        (
+            horizontal,
+            vertical,
+            self.size.0 - horizontal * 2,
+            self.size.1 - vertical * 2,
+        )
+    }
+}
+
+fn is_black(pixel: Rgba<u8>) -> bool {
+    pixel.0[..3]
+        .iter()
+        .all(|channel| *channel <= BLACK_THRESHOLD)
+}
+
+/// Measures the bars of a single frame, None when the frame is too dark to tell
+fn measure<I: GenericImageView<Pixel = Rgba<u8>>>(img: &I) -> Option<Borders> {
+    let (width, height) = img.dimensions();
+    let sample = |length: u32, i: u32| i * length.saturating_sub(1) / (SAMPLES - 1);
+    let row_black = |y: u32| (0..SAMPLES).all(|i| is_black(img.get_pixel(sample(width, i), y)));
+    let column_black = |x: u32| (0..SAMPLES).all(|i| is_black(img.get_pixel(x, sample(height, i))));
+
+    let max_rows = (height as f32 * MAX_BAR) as u32;
+    let max_columns = (width as f32 * MAX_BAR) as u32;
+    let top = (0..max_rows).take_while(|y| row_black(*y)).count() as u32;
+    let bottom = (0..max_rows)
+        .take_while(|y| row_black(height - 1 - y))
+        .count() as u32;
+    let left = (0..max_columns).take_while(|x| column_black(*x)).count() as u32;
+    let right = (0..max_columns)
+        .take_while(|x| column_black(width - 1 - x))
+        .count() as u32;
+
+    // Dark all the way in from both sides is a dark scene rather than a letterbox
+    if (max_rows > 0 && top == max_rows && bottom == max_rows)
+        || (max_columns > 0 && left == max_columns && right == max_columns)
+    {
+        return None;
+    }
+
+    // Bars are centered, a dark sky or floor only darkens one side
+    let vertical = top.min(bottom);
+    let horizontal = left.min(right);
+    Some(Borders {
+        vertical: match vertical as f32 >= height as f32 * MIN_BAR {
+            true => vertical,
+            false => 0,
+        },
+        horizontal: match horizontal as f32 >= width as f32 * MIN_BAR {
+            true => horizontal,
+            false => 0,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    const WIDTH: u32 = 200;
+    const HEIGHT: u32 = 100;
+
+    /// Gray picture with black bars of `bar` pixels at the top and bottom
+    fn letterboxed(bar: u32) -> RgbaImage {
+        RgbaImage::from_fn(WIDTH, HEIGHT, |_, y| match y < bar || y >= HEIGHT - bar {
+            true => Rgba([0, 0, 0, 255]),
+            false => Rgba([128, 128, 128, 255]),
+        })
+    }
+
+    fn feed(
+        detector: &mut LetterboxDetector,
+        img: &RgbaImage,
+        frames: u32,
+    ) -> (u32, u32, u32, u32) {
+        let mut crop = (0, 0, WIDTH, HEIGHT);
+        for _ in 0..frames {
+            crop = detector.update(img);
+        }
+        crop
+    }
+
+    #[test]
+    fn adopts_a_bar_once_it_is_stable() {
+        let mut detector = LetterboxDetector::default();
+        let img = letterboxed(12);
+
+        assert_eq!(
+            feed(&mut detector, &img, STABLE_FRAMES - 1),
+            (0, 0, WIDTH, HEIGHT)
+        );
+        assert_eq!(feed(&mut detector, &img, 1), (0, 12, WIDTH, HEIGHT - 24));
+    }
+
+    #[test]
+    fn dark_frames_keep_the_crop() {
+        let mut detector = LetterboxDetector::default();
+        let img = letterboxed(12);
+        let dark = RgbaImage::from_pixel(WIDTH, HEIGHT, Rgba([0, 0, 0, 255]));
+        feed(&mut detector, &img, STABLE_FRAMES);
+
+        for _ in 0..STABLE_FRAMES * 2 {
+            assert_eq!(detector.update(&dark), (0, 12, WIDTH, HEIGHT - 24));
+        }
+        assert_eq!(detector.update(&img), (0, 12, WIDTH, HEIGHT - 24));
+    }
+
+    #[test]
+    fn a_new_bar_size_replaces_the_crop() {
+        let mut detector = LetterboxDetector::default();
+        feed(&mut detector, &letterboxed(12), STABLE_FRAMES);
+
+        let img = letterboxed(24);
+        assert_eq!(
+            feed(&mut detector, &img, STABLE_FRAMES - 1),
+            (0, 12, WIDTH, HEIGHT - 24)
+        );
+        assert_eq!(feed(&mut detector, &img, 1), (0, 24, WIDTH, HEIGHT - 48));
+    }
+}
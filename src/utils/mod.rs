@@ -1,6 +1,25 @@
+pub mod ambilight;
+pub mod calibration;
 pub mod capture;
+pub mod change;
 pub mod config;
+pub mod definitions;
+pub mod device;
+pub mod downscale;
+pub mod firmware;
+pub mod flash;
+pub mod hotplug;
+pub mod interpolation;
+pub mod layout;
+pub mod letterbox;
+pub mod monitor;
+pub mod oklab;
+pub mod openrgb;
 pub mod paths;
+pub mod pipeline;
 pub mod plugins;
+pub mod recording;
+pub mod source;
 pub mod ui;
+pub mod window;
 pub mod wooting;
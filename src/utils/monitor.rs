@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use xcap::{Monitor, XCapResult};
+
+/// Identifies a monitor across restarts and replugs, the position in `Monitor::all()` changes too easily for that
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct MonitorIdentity {
+    pub name: String,
+    pub id: u32,
+    pub size: (u32, u32),
+    pub position: (i32, i32),
+}
+
+impl MonitorIdentity {
+    pub fn of(monitor: &Monitor) -> Option<Self> {
+        Some(Self {
+            name: monitor.name().ok()?,
+            id: monitor.id().ok()?,
+            size: (monitor.width().ok()?, monitor.height().ok()?),
+            position: (monitor.x().ok()?, monitor.y().ok()?),
+        })
+    }
+
+    /// Name and resolution as shown in the screen dropdown
+    pub fn label(&self) -> String {
+        format!("{} ({}x{})", self.name, self.size.0, self.size.1)
+    }
+}
+
+/// Every connected monitor with its identity, monitors that can not be identified are left out
+pub fn monitors() -> XCapResult<Vec<(Monitor, MonitorIdentity)>> {
+    Ok(Monitor::all()?
+        .into_iter()
+        .filter_map(|monitor| MonitorIdentity::of(&monitor).map(|identity| (monitor, identity)))
+        .collect())
+}
+
+/// Finds the saved monitor, or the one most likely to be it when it is missing, None means the primary monitor.
+///
+/// Candidates are tried in order: an exact match, the same name and resolution (the id changed), the same id (the
+/// resolution or position changed), the same position and resolution (the name changed after a driver update), the
+/// same name, then the primary monitor and finally the first one.
+pub fn find_monitor(
+    monitors: &[(Monitor, MonitorIdentity)],
+    saved: Option<&MonitorIdentity>,
+) -> Option<usize> {
+    let primary = || {
+        monitors
+            .iter()
+            .position(|(monitor, _)| monitor.is_primary().unwrap_or(false))
+            .or((!monitors.is_empty()).then_some(0))
+    };
+    let Some(saved) = saved else {
+        return primary();
+    };
+
+    let rules: [&dyn Fn(&MonitorIdentity) -> bool; 5] = [
+        &|identity| identity == saved,
+        &|identity| identity.name == saved.name && identity.size == saved.size,
+        &|identity| identity.id == saved.id,
+        &|identity| identity.position == saved.position && identity.size == saved.size,
+        &|identity| identity.name == saved.name,
+    ];
+
+    rules
+        .iter()
+        .find_map(|rule| monitors.iter().position(|(_, identity)| rule(identity)))
+        .or_else(primary)
+}
@@ -0,0 +1,51 @@
+/// Chroma of the most saturated sRGB colors, pure blue and magenta reach a little over this
+pub const MAX_CHROMA: f32 = 0.32;
+/// Lightness of sRGB middle gray (128, 128, 128)
+pub const MIDDLE_GRAY: f32 = 0.6;
+
+/// Oklab lightness, green-red and blue-yellow of an sRGB color with channels from 0 to 255, Oklab is a color space
+/// where equal steps look about equally different
+pub fn from_srgb(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(|channel| to_linear(channel / 255.0));
+
+    let l = (0.41222146 * r + 0.53633255 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171885 * g + 0.6299787 * b).cbrt();
+
+    [
+        0.21045426 * l + 0.7936178 * m - 0.00407205 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+/// sRGB color with channels from 0 to 255 of an Oklab color, colors outside of sRGB are clipped
+pub fn to_srgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.39633778 * a + 0.21580376 * b).powi(3);
+    let m = (lightness - 0.10556135 * a - 0.06385417 * b).powi(3);
+    let s = (lightness - 0.08948418 * a - 1.2914855 * b).powi(3);
+
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+        -1.268438 * l + 2.6097574 * m - 0.3413194 * s,
+        -0.00419609 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+    .map(|channel| from_linear(channel) * 255.0)
+}
+
+/// Linear light of a gamma encoded sRGB channel from 0 to 1
+pub fn to_linear(channel: f32) -> f32 {
+    let channel = channel.max(0.0);
+    match channel <= 0.04045 {
+        true => channel / 12.92,
+        false => ((channel + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn from_linear(channel: f32) -> f32 {
+    let channel = channel.clamp(0.0, 1.0);
+    match channel <= 0.0031308 {
+        true => channel * 12.92,
+        false => 1.055 * channel.powf(1.0 / 2.4) - 0.055,
+    }
+}
@@ -0,0 +1,546 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use scorched::{logf, LogData, LogImportance};
+
+use super::{
+    config::{OpenRgbConfig, OpenRgbTarget},
+    device::KeyFrame,
+};
+
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../examples/openrgb_stand_in.rs"]
+mod stand_in;
+
+/// Packet ids of the OpenRGB SDK protocol
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const SET_CLIENT_NAME: u32 = 50;
+const UPDATE_ZONE_LEDS: u32 = 1052;
+const SET_CUSTOM_MODE: u32 = 1100;
+
+const MAGIC: &[u8; 4] = b"ORGB";
+const HEADER_SIZE: usize = 16;
+/// Matrix map entries without an led
+const NO_LED: u32 = u32::MAX;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// Connecting runs on the capture thread, so an unreachable server may only hold up the keyboards for this long
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(250);
+/// Time to wait before trying to reach the server again after a failure
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Controller {
+    pub index: u32,
+    pub name: String,
+    pub zones: Vec<Zone>,
+}
+
+#[derive(Clone)]
+pub struct Zone {
+    pub index: u32,
+    pub name: String,
+    pub led_count: u32,
+    pub matrix: Option<ZoneMatrix>,
+}
+
+/// Position of the leds of a zone, `map[y * width + x]` is the led at (x, y)
+#[derive(Clone)]
+pub struct ZoneMatrix {
+    pub width: u32,
+    pub height: u32,
+    pub map: Vec<u32>,
+}
+
+/// Minimal client for the OpenRGB SDK server, only speaks protocol version 0 which every server supports
+pub struct OpenRgbClient {
+    stream: TcpStream,
+}
+
+impl OpenRgbClient {
+    pub fn connect(host: &str, port: u16) -> io::Result<Self> {
+        let mut last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            "The host did not resolve to any address",
+        );
+        let mut connected = None;
+        for address in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    connected = Some(stream);
+                    break;
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        let stream = connected.ok_or(last_error)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        let mut client = Self { stream };
+        client.send(0, SET_CLIENT_NAME, b"Wootili-View\0")?;
+
+        Ok(client)
+    }
+
+    fn send(&mut self, device: u32, packet_id: u32, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(HEADER_SIZE + data.len());
+        packet.extend_from_slice(MAGIC);
+        packet.extend_from_slice(&device.to_le_bytes());
+        packet.extend_from_slice(&packet_id.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(data);
+
+        self.stream.write_all(&packet)
+    }
+
+    /// Reads packets until one with `packet_id` arrives, the server may send device list updates in between
+    fn receive(&mut self, packet_id: u32) -> io::Result<Vec<u8>> {
+        loop {
+            let mut header = [0u8; HEADER_SIZE];
+            self.stream.read_exact(&mut header)?;
+
+            if &header[0..4] != MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid OpenRGB packet header",
+                ));
+            }
+
+            let id = u32::from_le_bytes(header[8..12].try_into().unwrap());
+            let size = u32::from_le_bytes(header[12..16].try_into().unwrap());
+
+            let mut data = vec![0u8; size as usize];
+            self.stream.read_exact(&mut data)?;
+
+            if id == packet_id {
+                return Ok(data);
+            }
+        }
+    }
+
+    pub fn controllers(&mut self) -> io::Result<Vec<Controller>> {
+        self.send(0, REQUEST_CONTROLLER_COUNT, &[])?;
+        let count = PacketReader::new(&self.receive(REQUEST_CONTROLLER_COUNT)?).u32()?;
+
+        (0..count)
+            .map(|index| {
+                self.send(index, REQUEST_CONTROLLER_DATA, &[])?;
+                read_controller(index, &self.receive(REQUEST_CONTROLLER_DATA)?)
+            })
+            .collect()
+    }
+
+    /// Switches the controller to its direct control mode so led updates are shown
+    pub fn set_custom_mode(&mut self, controller: u32) -> io::Result<()> {
+        self.send(controller, SET_CUSTOM_MODE, &[])
+    }
+
+    pub fn update_zone_leds(
+        &mut self,
+        controller: u32,
+        zone: u32,
+        colors: &[(u8, u8, u8)],
+    ) -> io::Result<()> {
+        let size = 4 + 4 + 2 + colors.len() * 4;
+        let mut data = Vec::with_capacity(size);
+        data.extend_from_slice(&(size as u32).to_le_bytes());
+        data.extend_from_slice(&zone.to_le_bytes());
+        data.extend_from_slice(&(colors.len() as u16).to_le_bytes());
+        for (r, g, b) in colors {
+            data.extend_from_slice(&[*r, *g, *b, 0]);
+        }
+
+        self.send(controller, UPDATE_ZONE_LEDS, &data)
+    }
+}
+
+/// Reads little endian values out of a packet, running past the end is an error
+struct PacketReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PacketReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "OpenRGB packet is shorter than expected",
+            ));
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Strings are sent as a u16 length followed by the null terminated text
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u16()? as usize;
+        let text = self.take(len)?;
+
+        Ok(String::from_utf8_lossy(text)
+            .trim_end_matches('\0')
+            .to_string())
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+}
+
+/// Parses the controller data packet of protocol version 0, only the name and zones are kept
+fn read_controller(index: u32, data: &[u8]) -> io::Result<Controller> {
+    let mut reader = PacketReader::new(data);
+
+    reader.skip(4)?; // Data size
+    reader.skip(4)?; // Device type
+    let name = reader.string()?;
+    for _ in 0..4 {
+        // Description, version, serial and location
+        reader.string()?;
+    }
+
+    let mode_count = reader.u16()?;
+    reader.skip(4)?; // Active mode
+    for _ in 0..mode_count {
+        reader.string()?;
+        // Value, flags, speed min and max, colors min and max, speed, direction and color mode
+        reader.skip(9 * 4)?;
+        let color_count = reader.u16()? as usize;
+        reader.skip(color_count * 4)?;
+    }
+
+    let zone_count = reader.u16()?;
+    let mut zones = Vec::with_capacity(zone_count as usize);
+    for zone_index in 0..zone_count {
+        let zone_name = reader.string()?;
+        reader.skip(4)?; // Zone type
+        reader.skip(8)?; // Leds min and max
+        let led_count = reader.u32()?;
+
+        let matrix = match reader.u16()? {
+            0 => None,
+            _ => {
+                let height = reader.u32()?;
+                let width = reader.u32()?;
+                let map = (0..width * height)
+                    .map(|_| reader.u32())
+                    .collect::<io::Result<Vec<u32>>>()?;
+
+                Some(ZoneMatrix { width, height, map })
+            }
+        };
+
+        zones.push(Zone {
+            index: zone_index as u32,
+            name: zone_name,
+            led_count,
+            matrix,
+        });
+    }
+
+    Ok(Controller { index, name, zones })
+}
+
+/// Spreads the lighting of a device over the leds of a zone, zones with a matrix sample the lighting at the position
+/// of each led and linear zones take the average of each column along their length.
+///
+/// `frame` is what the device was sent, its lighting covers `size` keys starting on matrix row `first_row`.
+pub fn zone_colors(
+    zone: &Zone,
+    frame: &KeyFrame,
+    size: (u32, u32),
+    first_row: u8,
+) -> Vec<(u8, u8, u8)> {
+    let mut colors = vec![(0, 0, 0); zone.led_count as usize];
+    let (frame_width, frame_height) = size;
+    if frame_width == 0 || frame_height == 0 {
+        return colors;
+    }
+
+    let get_pixel = |x: u32, y: u32| {
+        frame
+            .get(y as usize + first_row as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
+            .unwrap_or_default()
+    };
+
+    match &zone.matrix {
+        Some(matrix) => {
+            for y in 0..matrix.height {
+                for x in 0..matrix.width {
+                    let led = matrix.map[(y * matrix.width + x) as usize];
+                    let Some(color) = colors.get_mut(led as usize).filter(|_| led != NO_LED) else {
+                        continue;
+                    };
+
+                    *color = get_pixel(
+                        x * frame_width / matrix.width,
+                        y * frame_height / matrix.height,
+                    );
+                }
+            }
+        }
+        None => {
+            for (led, color) in colors.iter_mut().enumerate() {
+                let x = led as u32 * frame_width / zone.led_count;
+
+                let mut sum = [0u32; 3];
+                for y in 0..frame_height {
+                    let (r, g, b) = get_pixel(x, y);
+                    sum[0] += r as u32;
+                    sum[1] += g as u32;
+                    sum[2] += b as u32;
+                }
+
+                *color = (
+                    (sum[0] / frame_height) as u8,
+                    (sum[1] / frame_height) as u8,
+                    (sum[2] / frame_height) as u8,
+                );
+            }
+        }
+    }
+
+    colors
+}
+
+/// A zone a device's frames are sent to
+struct ResolvedTarget {
+    source: usize,
+    controller: u32,
+    zone: Zone,
+}
+
+/// Sends the frames of the capture loop to the zones mapped in the config, reconnects on its own when the server goes away
+pub struct OpenRgbOutput {
+    config: OpenRgbConfig,
+    client: Option<OpenRgbClient>,
+    targets: Vec<ResolvedTarget>,
+    last_attempt: Option<Instant>,
+}
+
+impl OpenRgbOutput {
+    pub fn new(config: OpenRgbConfig) -> Self {
+        Self {
+            config,
+            client: None,
+            targets: Vec::new(),
+            last_attempt: None,
+        }
+    }
+
+    /// Applies a new config, the connection is only dropped if the config actually changed
+    pub fn configure(&mut self, config: OpenRgbConfig) {
+        if config != self.config {
+            self.config = config;
+            self.client = None;
+            self.last_attempt = None;
+        }
+    }
+
+    /// Sends the frame the device at `source` was sent to every zone it is mapped to, see `zone_colors`
+    pub fn send(&mut self, source: usize, frame: &KeyFrame, size: (u32, u32), first_row: u8) {
+        if !self.config.enabled
+            || !self
+                .config
+                .targets
+                .iter()
+                .any(|target| target.source == source)
+        {
+            return;
+        }
+
+        if self.client.is_none() && !self.reconnect() {
+            return;
+        }
+
+        let client = self.client.as_mut().unwrap();
+        let result = self
+            .targets
+            .iter()
+            .filter(|target| target.source == source)
+            .try_for_each(|target| {
+                client.update_zone_leds(
+                    target.controller,
+                    target.zone.index,
+                    &zone_colors(&target.zone, frame, size, first_row),
+                )
+            });
+
+        if let Err(e) = result {
+            logf!(Warning, "Lost connection to the OpenRGB server: {}", e);
+            self.client = None;
+        }
+    }
+
+    /// Connects and resolves the mapping in the config against the controllers the server has, returns false while
+    /// the server can not be reached
+    fn reconnect(&mut self) -> bool {
+        if self
+            .last_attempt
+            .is_some_and(|last_attempt| last_attempt.elapsed() < RETRY_INTERVAL)
+        {
+            return false;
+        }
+        self.last_attempt = Some(Instant::now());
+
+        let result =
+            OpenRgbClient::connect(&self.config.host, self.config.port).and_then(|mut client| {
+                let controllers = client.controllers()?;
+                let targets = resolve_targets(&self.config.targets, controllers);
+
+                let mut controller_indices: Vec<u32> =
+                    targets.iter().map(|target| target.controller).collect();
+                controller_indices.sort();
+                controller_indices.dedup();
+                for controller in controller_indices {
+                    client.set_custom_mode(controller)?;
+                }
+
+                Ok((client, targets))
+            });
+
+        match result {
+            Ok((client, targets)) => {
+                logf!(
+                    Info,
+                    "Connected to the OpenRGB server at {}:{}, sending to {} zones",
+                    self.config.host,
+                    self.config.port,
+                    targets.len()
+                );
+                self.client = Some(client);
+                self.targets = targets;
+                true
+            }
+            Err(e) => {
+                logf!(
+                    Warning,
+                    "Unable to connect to the OpenRGB server at {}:{}: {}",
+                    self.config.host,
+                    self.config.port,
+                    e
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Matches the configured targets to controllers by name, a target without a zone covers every zone of the controller
+fn resolve_targets(targets: &[OpenRgbTarget], controllers: Vec<Controller>) -> Vec<ResolvedTarget> {
+    let mut resolved = Vec::new();
+
+    for target in targets {
+        let Some(controller) = controllers
+            .iter()
+            .find(|controller| controller.name == target.controller)
+        else {
+            logf!(
+                Warning,
+                "OpenRGB controller {} was not found on the server",
+                target.controller
+            );
+            continue;
+        };
+
+        let zones: Vec<&Zone> = controller
+            .zones
+            .iter()
+            .filter(|zone| target.zone.as_ref().is_none_or(|name| *name == zone.name))
+            .collect();
+        if zones.is_empty() {
+            logf!(
+                Warning,
+                "OpenRGB controller {} has no zone named {}",
+                target.controller,
+                target.zone.as_deref().unwrap_or_default()
+            );
+        }
+
+        for zone in zones {
+            resolved.push(ResolvedTarget {
+                source: target.source,
+                controller: controller.index,
+                zone: zone.clone(),
+            });
+        }
+    }
+
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::mpsc::channel};
+
+    use super::*;
+    use crate::utils::device::{MATRIX_COLUMNS, MATRIX_ROWS};
+
+    #[test]
+    fn sends_zone_leds_to_the_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, updates) = channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = stand_in::serve(stream, |update| {
+                let _ = sender.send(update);
+            });
+        });
+
+        let mut output = OpenRgbOutput::new(OpenRgbConfig {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port,
+            targets: vec![OpenRgbTarget {
+                source: 0,
+                controller: "Stand-in Device".to_string(),
+                zone: Some("Matrix".to_string()),
+            }],
+        });
+        // The lighting starts on the second matrix row, like on a 60% board
+        let mut frame = [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS];
+        for (y, row) in frame[1..3].iter_mut().enumerate() {
+            for (x, color) in row[..3].iter_mut().enumerate() {
+                *color = (x as u8 * 50, y as u8 * 100, 5);
+            }
+        }
+        output.send(0, &frame, (3, 2), 1);
+
+        let update = updates.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(update.controller, 0);
+        assert_eq!(update.zone, 0);
+        assert_eq!(
+            update.colors,
+            vec![
+                (0, 0, 5),
+                (50, 0, 5),
+                (100, 0, 5),
+                (0, 100, 5),
+                (50, 100, 5),
+                (100, 100, 5)
+            ]
+        );
+    }
+}
@@ -34,3 +34,13 @@ pub fn config_path() -> PathBuf {
         }
     }
 }
+
+/// Folder for user supplied device definitions
+pub fn definitions_path() -> PathBuf {
+    config_path().join("devices")
+}
+
+/// Folder the lighting recordings are saved to
+pub fn recordings_path() -> PathBuf {
+    config_path().join("recordings")
+}
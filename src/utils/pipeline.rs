@@ -0,0 +1,403 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use super::{calibration::Calibration, flash::FlashLimiter, oklab};
+
+/// Color of a single key on its way to the device, channels go from 0 to 255 but may leave that range between stages
+#[derive(Clone, Copy, PartialEq)]
+pub struct Key {
+    pub row: u8,
+    pub column: u8,
+    pub color: [f32; 3],
+    /// The led is not covered by a keyswitch, like the 60HE spacebar area, so the keycaps do not tint it
+    pub exposed: bool,
+}
+
+/// A step of the color pipeline that runs between downscaling and the device, stages may keep state between frames
+pub trait ColorStage: Send {
+    fn apply(&mut self, keys: &mut [Key]);
+
+    /// False while the stage would still change the colors of a frame that is drawn again
+    fn settled(&self) -> bool {
+        true
+    }
+
+    /// Takes the parameters of `stage` while keeping the state built up so far, false when the stage keeps no state
+    /// and is built again instead
+    fn reconfigure(&mut self, _stage: &StageKind) -> bool {
+        false
+    }
+}
+
+/// Eases every key towards its new color instead of jumping to it, `attack` and `decay` are how many seconds it
+/// takes a key to get about two thirds of the way when it gets brighter and darker
+pub struct Smoothing {
+    pub attack: f32,
+    pub decay: f32,
+    colors: Vec<[f32; 3]>,
+    last_update: Instant,
+    settled: bool,
+}
+
+impl Smoothing {
+    pub fn new(attack: f32, decay: f32) -> Self {
+        Self {
+            attack,
+            decay,
+            colors: Vec::new(),
+            last_update: Instant::now(),
+            settled: true,
+        }
+    }
+}
+
+impl ColorStage for Smoothing {
+    fn apply(&mut self, keys: &mut [Key]) {
+        let elapsed = self.last_update.elapsed().as_secs_f32();
+        self.last_update = Instant::now();
+
+        // Starts from the first frame of a new size instead of fading in from black
+        if self.colors.len() != keys.len() {
+            self.colors = keys.iter().map(|key| key.color).collect();
+            return;
+        }
+
+        let luma = |[r, g, b]: [f32; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        self.settled = true;
+        for (key, color) in keys.iter_mut().zip(self.colors.iter_mut()) {
+            let time = match luma(key.color) > luma(*color) {
+                true => self.attack,
+                false => self.decay,
+            };
+            let step = match time > 0.0 {
+                true => 1.0 - (-elapsed / time).exp(),
+                false => 1.0,
+            };
+
+            for (current, target) in color.iter_mut().zip(key.color) {
+                *current += (target - *current) * step;
+                self.settled &= (target - *current).abs() < 0.5;
+            }
+            key.color = *color;
+        }
+    }
+
+    fn settled(&self) -> bool {
+        self.settled
+    }
+
+    fn reconfigure(&mut self, stage: &StageKind) -> bool {
+        match stage {
+            StageKind::Smoothing { attack, decay } => {
+                self.attack = *attack;
+                self.decay = *decay;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Scales every channel by a factor
+pub struct Brightness(pub f32);
+
+impl ColorStage for Brightness {
+    fn apply(&mut self, keys: &mut [Key]) {
+        for key in keys {
+            key.color = key.color.map(|channel| channel * self.0);
+        }
+    }
+}
+
+/// Raises each channel to a power, above 1 darkens the midtones and below 1 brightens them
+pub struct Gamma(pub f32);
+
+impl ColorStage for Gamma {
+    fn apply(&mut self, keys: &mut [Key]) {
+        for key in keys {
+            key.color = key
+                .color
+                .map(|channel| (channel.max(0.0) / 255.0).powf(self.0) * 255.0);
+        }
+    }
+}
+
+/// Changes the Oklab chroma of each key by `chroma(current chroma)`, keeping its lightness and hue
+fn map_chroma(keys: &mut [Key], chroma: impl Fn(f32) -> f32) {
+    for key in keys {
+        let [lightness, a, b] = oklab::from_srgb(key.color);
+        let current = a.hypot(b);
+        if current <= f32::EPSILON {
+            continue;
+        }
+
+        let scale = chroma(current) / current;
+        key.color = oklab::to_srgb([lightness, a * scale, b * scale]);
+    }
+}
+
+/// Scales the chroma of every color, 0 turns them gray and 1 leaves them alone
+pub struct Saturation(pub f32);
+
+impl ColorStage for Saturation {
+    fn apply(&mut self, keys: &mut [Key]) {
+        map_chroma(keys, |chroma| chroma * self.0);
+    }
+}
+
+/// Raises the chroma of dull colors while leaving colors that are already saturated mostly alone, negative values
+/// mute dull colors instead
+pub struct Vibrance(pub f32);
+
+impl ColorStage for Vibrance {
+    fn apply(&mut self, keys: &mut [Key]) {
+        map_chroma(keys, |chroma| {
+            let dullness = (1.0 - chroma / oklab::MAX_CHROMA).clamp(0.0, 1.0);
+            chroma * (1.0 + self.0 * dullness).max(0.0)
+        });
+    }
+}
+
+/// Spreads the Oklab lightness away from middle gray by a factor, 1 leaves it alone
+pub struct Contrast(pub f32);
+
+impl ColorStage for Contrast {
+    fn apply(&mut self, keys: &mut [Key]) {
+        for key in keys {
+            let [lightness, a, b] = oklab::from_srgb(key.color);
+            let lightness = oklab::MIDDLE_GRAY + (lightness - oklab::MIDDLE_GRAY) * self.0;
+            key.color = oklab::to_srgb([lightness.max(0.0), a, b]);
+        }
+    }
+}
+
+/// Multiplies each channel, exposed leds are left alone since they are not tinted by the keycaps
+pub struct ChannelGains(pub [f32; 3]);
+
+impl ColorStage for ChannelGains {
+    fn apply(&mut self, keys: &mut [Key]) {
+        for key in keys.iter_mut().filter(|key| !key.exposed) {
+            key.color = [0, 1, 2].map(|channel| key.color[channel] * self.0[channel]);
+        }
+    }
+}
+
+/// Applies the calibration of a device, exposed leds are left alone since they are not tinted by the keycaps
+pub struct Calibrate(pub Calibration);
+
+impl ColorStage for Calibrate {
+    fn apply(&mut self, keys: &mut [Key]) {
+        for key in keys.iter_mut().filter(|key| !key.exposed) {
+            key.color = self.0.apply(key.color);
+        }
+    }
+}
+
+/// Sets single keys to a fixed color
+pub struct Overrides(pub Vec<KeyOverride>);
+
+impl ColorStage for Overrides {
+    fn apply(&mut self, keys: &mut [Key]) {
+        for key in keys {
+            if let Some(key_override) = self.0.iter().find(|key_override| {
+                (key_override.row, key_override.column) == (key.row, key.column)
+            }) {
+                key.color = key_override.color.map(|channel| channel as f32);
+            }
+        }
+    }
+}
+
+/// Key set to a fixed color by an `Overrides` stage, the row is the row of the lighting matrix
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeyOverride {
+    pub row: u8,
+    pub column: u8,
+    pub color: [u8; 3],
+}
+
+/// A stage as saved in the config
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum StageKind {
+    /// Uses the brightness of the device
+    Brightness,
+    Gamma(f32),
+    Saturation(f32),
+    Vibrance(f32),
+    Contrast(f32),
+    ChannelGains([f32; 3]),
+    /// Uses the calibration of the device
+    Calibration,
+    Overrides(Vec<KeyOverride>),
+    /// Caps how often the lighting flashes, a flash being a rise and fall of the luminance by `threshold`
+    FlashLimiter {
+        flashes_per_second: f32,
+        threshold: f32,
+    },
+    /// Seconds to fade towards brighter and darker colors
+    Smoothing {
+        attack: f32,
+        decay: f32,
+    },
+}
+
+/// The stage behind Reduce Bright Effects, three flashes a second of a tenth of the luminance range as in WCAG 2.3.1
+pub const REDUCE_BRIGHT_EFFECTS: StageKind = StageKind::FlashLimiter {
+    flashes_per_second: 3.0,
+    threshold: 0.1,
+};
+
+impl StageKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StageKind::Brightness => "Brightness",
+            StageKind::Gamma(_) => "Gamma",
+            StageKind::Saturation(_) => "Saturation",
+            StageKind::Vibrance(_) => "Vibrance",
+            StageKind::Contrast(_) => "Contrast",
+            StageKind::ChannelGains(_) => "Channel Gains",
+            StageKind::Calibration => "Calibration",
+            StageKind::Overrides(_) => "Overrides",
+            StageKind::Smoothing { .. } => "Smoothing",
+            StageKind::FlashLimiter { .. } => "Flash Limiter",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct StageConfig {
+    pub stage: StageKind,
+    pub enabled: bool,
+}
+
+/// Stages of a new device, the picture is adjusted before the corrections for the device are applied
+pub fn default_stages() -> Vec<StageConfig> {
+    let stage = |stage, enabled| StageConfig { stage, enabled };
+    let red = |row, column| KeyOverride {
+        row,
+        column,
+        color: [255, 0, 0],
+    };
+
+    vec![
+        stage(
+            StageKind::Smoothing {
+                attack: 0.05,
+                decay: 0.3,
+            },
+            false,
+        ),
+        stage(StageKind::Contrast(1.0), false),
+        stage(StageKind::Saturation(1.0), false),
+        stage(StageKind::Vibrance(0.0), false),
+        stage(StageKind::Gamma(1.0), false),
+        stage(StageKind::Brightness, true),
+        stage(REDUCE_BRIGHT_EFFECTS, false),
+        // Color Calibration, which replaced the Red Shift Fix
+        stage(StageKind::Calibration, false),
+        // Highlight WASD
+        stage(
+            StageKind::Overrides(vec![red(2, 2), red(3, 1), red(3, 2), red(3, 3)]),
+            false,
+        ),
+    ]
+}
+
+/// Parameter of the first stage it can be found in along with the stage, used by the sliders that change a single stage
+pub fn stage_value(
+    stages: &mut [StageConfig],
+    value: fn(&mut StageKind) -> Option<&mut f32>,
+) -> Option<(&mut f32, &mut bool)> {
+    stages.iter_mut().find_map(|stage| {
+        let StageConfig { stage, enabled } = stage;
+        value(stage).map(|value| (value, enabled))
+    })
+}
+
+/// Enabled flag of the first stage that `matches`, used by the settings that turn a single stage on and off
+pub fn stage_enabled(
+    stages: &mut [StageConfig],
+    matches: fn(&StageKind) -> bool,
+) -> Option<&mut bool> {
+    stages
+        .iter_mut()
+        .find(|stage| matches(&stage.stage))
+        .map(|stage| &mut stage.enabled)
+}
+
+fn build_stage(stage: &StageKind, brightness: u8, calibration: Calibration) -> Box<dyn ColorStage> {
+    match stage {
+        StageKind::Brightness => Box::new(Brightness(brightness as f32 * 0.01)),
+        StageKind::Gamma(gamma) => Box::new(Gamma(*gamma)),
+        StageKind::Saturation(saturation) => Box::new(Saturation(*saturation)),
+        StageKind::Vibrance(vibrance) => Box::new(Vibrance(*vibrance)),
+        StageKind::Contrast(contrast) => Box::new(Contrast(*contrast)),
+        StageKind::ChannelGains(gains) => Box::new(ChannelGains(*gains)),
+        StageKind::Calibration => Box::new(Calibrate(calibration)),
+        StageKind::Overrides(overrides) => Box::new(Overrides(overrides.clone())),
+        StageKind::FlashLimiter {
+            flashes_per_second,
+            threshold,
+        } => Box::new(FlashLimiter::new(*flashes_per_second, *threshold)),
+        StageKind::Smoothing { attack, decay } => Box::new(Smoothing::new(*attack, *decay)),
+    }
+}
+
+/// The enabled stages of a device in order
+pub struct ColorPipeline {
+    stages: Vec<Box<dyn ColorStage>>,
+    /// Kind of each stage, used to tell whether new settings still have the same stages
+    kinds: Vec<std::mem::Discriminant<StageKind>>,
+}
+
+impl ColorPipeline {
+    pub fn new(stages: &[StageConfig], brightness: u8, calibration: Calibration) -> Self {
+        let enabled = stages.iter().filter(|stage| stage.enabled);
+
+        Self {
+            stages: enabled
+                .clone()
+                .map(|stage| build_stage(&stage.stage, brightness, calibration))
+                .collect(),
+            kinds: enabled
+                .map(|stage| std::mem::discriminant(&stage.stage))
+                .collect(),
+        }
+    }
+
+    /// Applies new settings, stages keep their state as long as the same stages are enabled in the same order.
+    /// A flash limiter that forgot the last second could be bypassed by changing a setting during a strobe.
+    pub fn update(&mut self, stages: &[StageConfig], brightness: u8, calibration: Calibration) {
+        let enabled: Vec<&StageKind> = stages
+            .iter()
+            .filter(|stage| stage.enabled)
+            .map(|stage| &stage.stage)
+            .collect();
+        let same_stages = enabled
+            .iter()
+            .map(|stage| std::mem::discriminant(*stage))
+            .eq(self.kinds.iter().copied());
+        if !same_stages {
+            *self = Self::new(stages, brightness, calibration);
+            return;
+        }
+
+        for (current, stage) in self.stages.iter_mut().zip(enabled) {
+            if !current.reconfigure(stage) {
+                *current = build_stage(stage, brightness, calibration);
+            }
+        }
+    }
+
+    /// False while drawing the same frame again would still change the lighting
+    pub fn settled(&self) -> bool {
+        self.stages.iter().all(|stage| stage.settled())
+    }
+
+    pub fn run(&mut self, keys: &mut [Key]) {
+        for stage in self.stages.iter_mut() {
+            stage.apply(keys);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use scorched::{logf, LogData, LogImportance};
+
+use super::{
+    capture::CAPTURE_PREVIEW,
+    device::{KeyFrame, RgbDevice, MATRIX_COLUMNS, MATRIX_ROWS},
+    paths,
+};
+
+const MAGIC: &[u8; 4] = b"WVRC";
+const FORMAT_VERSION: u8 = 1;
+const RECORDING_EXTENSION: &str = "wvrec";
+
+/// Set from the UI, every `RecordingDevice` starts or stops recording on its next frame
+pub static RECORDING: AtomicBool = AtomicBool::new(false);
+
+/// What a recording was made on, stored at the start of the file
+#[derive(Clone)]
+pub struct RecordingHeader {
+    pub model: String,
+    pub rgb_size: (u8, u8),
+    pub first_row: u8,
+}
+
+/// Writes key frames to a file.
+///
+/// The file starts with `WVRC`, the format version, the model name (u8 length then text), the lighting size as
+/// (columns, rows) and the first row, each a u8. It is followed by the frames, each being the milliseconds since the
+/// start of the recording as a little endian u32, the number of keys that changed since the previous frame as a u8
+/// and then every changed key as (row, column, red, green, blue).
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+    last_frame: KeyFrame,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, header: &RecordingHeader) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let model = header.model.as_bytes();
+        let model = &model[..model.len().min(u8::MAX as usize)];
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION, model.len() as u8])?;
+        writer.write_all(model)?;
+        writer.write_all(&[header.rgb_size.0, header.rgb_size.1, header.first_row])?;
+
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+            last_frame: [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS],
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &KeyFrame) -> io::Result<()> {
+        let mut changed = Vec::new();
+        for (row, (keys, last_keys)) in frame.iter().zip(self.last_frame.iter()).enumerate() {
+            for (column, (key, last_key)) in keys.iter().zip(last_keys.iter()).enumerate() {
+                if key != last_key {
+                    changed.extend_from_slice(&[row as u8, column as u8, key.0, key.1, key.2]);
+                }
+            }
+        }
+
+        let timestamp = self.start.elapsed().as_millis() as u32;
+        self.writer.write_all(&timestamp.to_le_bytes())?;
+        self.writer.write_all(&[(changed.len() / 5) as u8])?;
+        self.writer.write_all(&changed)?;
+
+        self.last_frame = *frame;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Reads a recording made by `Recorder`, returns the header and every frame with the time it was shown at
+pub fn read_recording(path: &Path) -> io::Result<(RecordingHeader, Vec<(Duration, KeyFrame)>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("Not a Wootili-View recording"));
+    }
+
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    if bytes[0] != FORMAT_VERSION {
+        return Err(invalid("Unsupported recording format version"));
+    }
+
+    let mut model = vec![0u8; bytes[1] as usize];
+    reader.read_exact(&mut model)?;
+    let mut size = [0u8; 3];
+    reader.read_exact(&mut size)?;
+
+    let header = RecordingHeader {
+        model: String::from_utf8_lossy(&model).to_string(),
+        rgb_size: (size[0], size[1]),
+        first_row: size[2],
+    };
+
+    let mut frames = Vec::new();
+    let mut frame: KeyFrame = [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS];
+    loop {
+        let mut timestamp = [0u8; 4];
+        match reader.read_exact(&mut timestamp) {
+            Ok(_) => {}
+            // A recording that was cut off still plays up to its last whole frame
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let mut count = [0u8; 1];
+        reader.read_exact(&mut count)?;
+        let mut changed = vec![0u8; count[0] as usize * 5];
+        reader.read_exact(&mut changed)?;
+
+        for key in changed.chunks(5) {
+            if let Some(color) = frame
+                .get_mut(key[0] as usize)
+                .and_then(|row| row.get_mut(key[1] as usize))
+            {
+                *color = (key[2], key[3], key[4]);
+            }
+        }
+
+        frames.push((
+            Duration::from_millis(u32::from_le_bytes(timestamp) as u64),
+            frame,
+        ));
+    }
+
+    Ok((header, frames))
+}
+
+/// Passes everything through to the wrapped device and records the frames it is sent while `RECORDING` is set
+pub struct RecordingDevice {
+    inner: Box<dyn RgbDevice>,
+    recorder: Option<Recorder>,
+    pending: KeyFrame,
+}
+
+impl RecordingDevice {
+    pub fn new(inner: Box<dyn RgbDevice>) -> Self {
+        Self {
+            inner,
+            recorder: None,
+            pending: [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS],
+        }
+    }
+
+    fn start_recording(&mut self) {
+        let model = self.inner.name();
+        let rgb_size = self.inner.rgb_size().unwrap_or((0, 0));
+        let header = RecordingHeader {
+            first_row: self.inner.first_row(),
+            rgb_size: (rgb_size.0 as u8, rgb_size.1 as u8),
+            model,
+        };
+
+        let path = recording_path(&header.model);
+        match std::fs::create_dir_all(paths::recordings_path())
+            .and_then(|_| Recorder::create(&path, &header))
+        {
+            Ok(recorder) => {
+                logf!(Info, "Recording {} to {}", header.model, path.display());
+                self.recorder = Some(recorder);
+            }
+            Err(e) => {
+                logf!(
+                    Error,
+                    "Unable to start recording to {}: {}",
+                    path.display(),
+                    e
+                );
+                RECORDING.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A new file in the recordings folder named after the model and the current time
+fn recording_path(model: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let model: String = model
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+
+    paths::recordings_path().join(format!("{}-{}.{}", model, timestamp, RECORDING_EXTENSION))
+}
+
+impl RgbDevice for RecordingDevice {
+    fn connect(&mut self) -> bool {
+        self.inner.connect()
+    }
+
+    fn disconnect(&mut self) {
+        // The recording of a device ends when it goes away
+        self.recorder = None;
+        self.inner.disconnect();
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn rgb_size(&self) -> Option<(u32, u32)> {
+        self.inner.rgb_size()
+    }
+
+    fn first_row(&self) -> u8 {
+        self.inner.first_row()
+    }
+
+    fn set_key(&mut self, row: u8, column: u8, color: (u8, u8, u8)) {
+        if let Some(key) = self
+            .pending
+            .get_mut(row as usize)
+            .and_then(|row| row.get_mut(column as usize))
+        {
+            *key = color;
+        }
+
+        self.inner.set_key(row, column, color);
+    }
+
+    fn flush(&mut self) -> bool {
+        match (RECORDING.load(Ordering::Relaxed), self.recorder.is_some()) {
+            (true, false) => self.start_recording(),
+            (false, true) => {
+                logf!(Info, "Stopped recording {}", self.inner.name());
+                self.recorder = None;
+            }
+            _ => {}
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.write_frame(&self.pending) {
+                logf!(Error, "Unable to write recording, stopping it: {}", e);
+                self.recorder = None;
+                RECORDING.store(false, Ordering::Relaxed);
+            }
+        }
+
+        self.inner.flush()
+    }
+}
+
+/// Turns a key frame back into the downscaled frame layout used by the preview
+pub fn frame_to_image(frame: &KeyFrame, header: &RecordingHeader) -> DynamicImage {
+    let mut image = RgbaImage::new(header.rgb_size.0 as u32, header.rgb_size.1 as u32);
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (r, g, b) = frame
+            .get(y as usize + header.first_row as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
+            .unwrap_or((0, 0, 0));
+        *pixel = Rgba([r, g, b, 255]);
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Plays a recording onto `device` and the preview of the first device at its original timing, over and over
+pub fn replay(path: &Path, mut device: Box<dyn RgbDevice>) {
+    let (header, frames) = match read_recording(path) {
+        Ok(recording) => recording,
+        Err(e) => {
+            logf!(Error, "Unable to read recording {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    if frames.is_empty() {
+        logf!(Warning, "Recording {} has no frames", path.display());
+        return;
+    }
+
+    logf!(
+        Info,
+        "Replaying {} frames of {} from {}",
+        frames.len(),
+        header.model,
+        path.display()
+    );
+    device.connect();
+    *CAPTURE_PREVIEW.write().unwrap() = vec![None];
+
+    loop {
+        let start = Instant::now();
+
+        for (timestamp, frame) in frames.iter() {
+            if let Some(wait) = timestamp.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+
+            for (row, keys) in frame.iter().enumerate() {
+                for (column, color) in keys.iter().enumerate() {
+                    device.set_key(row as u8, column as u8, *color);
+                }
+            }
+            device.flush();
+
+            CAPTURE_PREVIEW.write().unwrap()[0] = Some(frame_to_image(frame, &header));
+        }
+    }
+}
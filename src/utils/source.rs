@@ -0,0 +1,571 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, Rgba, RgbaImage,
+};
+use scorched::{logf, LogData, LogImportance};
+use xcap::{Monitor, XCapError};
+
+use super::{
+    monitor::{self, MonitorIdentity},
+    window::WindowTracker,
+};
+
+/// A captured image and when it was taken
+#[derive(Clone)]
+pub struct Frame {
+    pub image: DynamicImage,
+    pub timestamp: Instant,
+}
+
+impl Frame {
+    pub fn new(image: DynamicImage) -> Self {
+        Self {
+            image,
+            timestamp: Instant::now(),
+        }
+    }
+}
+
+/// Why a source has no image
+#[derive(Clone, PartialEq)]
+pub enum CaptureError {
+    /// No monitor is connected at all
+    NoMonitor,
+    /// The system refused the capture, for example while the session is locked
+    PermissionDenied,
+    /// Any other error from the system, with its message
+    CaptureFailed(String),
+    /// The window is closed or minimized
+    WindowNotFound(String),
+    /// An image file or directory could not be read
+    Unreadable(String),
+}
+
+/// HRESULT of a refused call on Windows
+#[cfg(target_os = "windows")]
+const E_ACCESSDENIED: i32 = 0x80070005_u32 as i32;
+
+impl CaptureError {
+    /// Refused captures are recognised by the OS error xcap wraps, errors xcap only reports as text are never
+    /// guessed from their wording and count as a failed capture
+    fn from_xcap(error: XCapError) -> Self {
+        let denied = match &error {
+            #[cfg(target_os = "linux")]
+            XCapError::StdIOError(e) => e.kind() == std::io::ErrorKind::PermissionDenied,
+            #[cfg(target_os = "linux")]
+            XCapError::DbusError(e) => e.name() == Some("org.freedesktop.DBus.Error.AccessDenied"),
+            #[cfg(target_os = "windows")]
+            XCapError::WindowsCoreError(e) => e.code().0 == E_ACCESSDENIED,
+            _ => false,
+        };
+
+        match denied {
+            true => CaptureError::PermissionDenied,
+            false => CaptureError::CaptureFailed(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::NoMonitor => write!(f, "No screen is connected"),
+            CaptureError::PermissionDenied => write!(f, "Screen capture is not allowed right now"),
+            CaptureError::CaptureFailed(reason) => write!(f, "Capture failed: {}", reason),
+            CaptureError::WindowNotFound(query) => write!(f, "No window matches {}", query),
+            CaptureError::Unreadable(reason) => write!(f, "Unable to read images: {}", reason),
+        }
+    }
+}
+
+/// Anything that can produce images for the capture loop, the capture loop only reads images through this
+pub trait CaptureSource {
+    /// Takes the next image
+    fn next_frame(&mut self) -> Result<Frame, CaptureError>;
+}
+
+/// Which source to capture from, sources are shared between devices using the same kind
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum SourceKind {
+    /// The saved monitor, or the primary one when None
+    Monitor(Option<MonitorIdentity>),
+    /// Every monitor stitched together at their positions
+    Desktop,
+    /// Title or app name of a window
+    Window(String),
+    Image(PathBuf),
+    /// Directory of images shown one per frame in name order
+    Sequence(PathBuf),
+    Pattern(TestPattern),
+}
+
+impl SourceKind {
+    /// Parses a source given on the command line, `monitor:<name or index>`, `desktop`, `window:<title>`, `image:<path>`,
+    /// `sequence:<directory>` or `pattern:<bars|sweep|cycle>`
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, value) = spec.split_once(':').unwrap_or((spec, ""));
+
+        match kind {
+            "monitor" => {
+                let monitors = monitor::monitors().unwrap_or_default();
+                let position = value.parse::<usize>().ok().or_else(|| {
+                    monitors
+                        .iter()
+                        .position(|(_, identity)| identity.name == value)
+                })?;
+                let (_, identity) = monitors.get(position)?;
+                Some(SourceKind::Monitor(Some(identity.clone())))
+            }
+            "desktop" => Some(SourceKind::Desktop),
+            "window" => Some(SourceKind::Window(value.to_string())),
+            "image" => Some(SourceKind::Image(PathBuf::from(value))),
+            "sequence" => Some(SourceKind::Sequence(PathBuf::from(value))),
+            "pattern" => TestPattern::parse(value).map(SourceKind::Pattern),
+            _ => None,
+        }
+    }
+
+    pub fn open(&self) -> Box<dyn CaptureSource> {
+        match self {
+            SourceKind::Monitor(saved) => Box::new(MonitorSource::new(saved.clone())),
+            SourceKind::Desktop => Box::new(DesktopSource::new()),
+            SourceKind::Window(query) => Box::new(WindowTracker::new(query)),
+            SourceKind::Image(path) => Box::new(ImageSource::new(path)),
+            SourceKind::Sequence(path) => Box::new(SequenceSource::new(path)),
+            SourceKind::Pattern(pattern) => Box::new(PatternSource::new(*pattern)),
+        }
+    }
+}
+
+/// Time between looking for the saved monitor while another one is captured in its place
+const MONITOR_LOOKUP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A single monitor, looked up again whenever capturing it fails
+pub struct MonitorSource {
+    saved: Option<MonitorIdentity>,
+    monitor: Option<Monitor>,
+    current: Option<MonitorIdentity>,
+    last_lookup: Instant,
+}
+
+impl MonitorSource {
+    /// Captures the saved monitor, or the primary one when None
+    pub fn new(saved: Option<MonitorIdentity>) -> Self {
+        Self {
+            saved,
+            monitor: None,
+            current: None,
+            last_lookup: Instant::now(),
+        }
+    }
+
+    fn lookup(&mut self) -> Result<Monitor, CaptureError> {
+        self.last_lookup = Instant::now();
+        let mut monitors = monitor::monitors().map_err(CaptureError::from_xcap)?;
+        let index =
+            monitor::find_monitor(&monitors, self.saved.as_ref()).ok_or(CaptureError::NoMonitor)?;
+        let (monitor, identity) = monitors.swap_remove(index);
+
+        if self.current.as_ref() != Some(&identity) {
+            match self.saved.as_ref().filter(|saved| **saved != identity) {
+                Some(saved) => logf!(
+                    Warning,
+                    "Screen {} is missing, capturing {} instead",
+                    saved.label(),
+                    identity.label()
+                ),
+                None => logf!(Info, "Capturing screen {}", identity.label()),
+            }
+            self.current = Some(identity);
+        }
+
+        Ok(monitor)
+    }
+}
+
+impl CaptureSource for MonitorSource {
+    fn next_frame(&mut self) -> Result<Frame, CaptureError> {
+        // Goes back to the saved monitor once it is connected again
+        let standing_in = self.saved.is_some() && self.current != self.saved;
+        if standing_in && self.last_lookup.elapsed() >= MONITOR_LOOKUP_INTERVAL {
+            self.monitor = None;
+        }
+
+        let monitor = match self.monitor.take() {
+            Some(monitor) => monitor,
+            None => self.lookup()?,
+        };
+
+        // The monitor is only kept while capturing it works
+        let capture = monitor.capture_image().map_err(CaptureError::from_xcap)?;
+        self.monitor = Some(monitor);
+
+        Ok(Frame::new(DynamicImage::ImageRgba8(capture)))
+    }
+}
+
+/// Every monitor stitched together at its position in the desktop, gaps between monitors are left black.
+///
+/// Downscaling the result makes each part of the keyboard follow the screen physically above it. The monitors are
+/// listed again whenever capturing one of them fails.
+pub struct DesktopSource {
+    monitors: Option<Vec<Monitor>>,
+}
+
+impl DesktopSource {
+    pub fn new() -> Self {
+        Self { monitors: None }
+    }
+}
+
+impl CaptureSource for DesktopSource {
+    fn next_frame(&mut self) -> Result<Frame, CaptureError> {
+        let monitors = match self.monitors.take() {
+            Some(monitors) => monitors,
+            None => Monitor::all().map_err(CaptureError::from_xcap)?,
+        };
+        let bounds: Vec<(i32, i32, u32, u32)> = monitors
+            .iter()
+            .map(|monitor| {
+                (
+                    monitor.x().unwrap_or(0),
+                    monitor.y().unwrap_or(0),
+                    monitor.width().unwrap_or(1),
+                    monitor.height().unwrap_or(1),
+                )
+            })
+            .collect();
+
+        let no_monitor = || CaptureError::NoMonitor;
+        let left = bounds
+            .iter()
+            .map(|bound| bound.0)
+            .min()
+            .ok_or_else(no_monitor)?;
+        let top = bounds
+            .iter()
+            .map(|bound| bound.1)
+            .min()
+            .ok_or_else(no_monitor)?;
+        let right = bounds
+            .iter()
+            .map(|bound| bound.0 + bound.2 as i32)
+            .max()
+            .ok_or_else(no_monitor)?;
+        let bottom = bounds
+            .iter()
+            .map(|bound| bound.1 + bound.3 as i32)
+            .max()
+            .ok_or_else(no_monitor)?;
+
+        let mut desktop =
+            RgbaImage::new((right - left).max(1) as u32, (bottom - top).max(1) as u32);
+        // Screens that fail are left black as long as at least one of them works
+        let mut captured = false;
+        let mut error = None;
+        for (monitor, (x, y, width, height)) in monitors.iter().zip(bounds) {
+            let screen = match monitor.capture_image() {
+                Ok(screen) => screen,
+                Err(e) => {
+                    error = Some(CaptureError::from_xcap(e));
+                    continue;
+                }
+            };
+            captured = true;
+
+            // Captures are in physical pixels while positions are in logical ones on scaled screens
+            let screen = match screen.dimensions() == (width, height) {
+                true => screen,
+                false => imageops::resize(&screen, width, height, FilterType::Triangle),
+            };
+
+            imageops::overlay(&mut desktop, &screen, (x - left) as i64, (y - top) as i64);
+        }
+
+        // The monitors are only kept while every one of them can be captured
+        if error.is_none() {
+            self.monitors = Some(monitors);
+        }
+
+        match (captured, error) {
+            (false, Some(error)) => Err(error),
+            _ => Ok(Frame::new(DynamicImage::ImageRgba8(desktop))),
+        }
+    }
+}
+
+/// The same image every frame, loaded once
+pub struct ImageSource {
+    image: Result<DynamicImage, CaptureError>,
+}
+
+impl ImageSource {
+    pub fn new(path: &Path) -> Self {
+        let image = image::open(path)
+            .map_err(|e| CaptureError::Unreadable(format!("{}: {}", path.display(), e)));
+
+        Self { image }
+    }
+}
+
+impl CaptureSource for ImageSource {
+    fn next_frame(&mut self) -> Result<Frame, CaptureError> {
+        self.image.clone().map(Frame::new)
+    }
+}
+
+/// Every image in a directory in name order, one per frame and starting over after the last one
+pub struct SequenceSource {
+    path: PathBuf,
+    paths: Vec<PathBuf>,
+    position: usize,
+}
+
+impl SequenceSource {
+    pub fn new(path: &Path) -> Self {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| image::ImageFormat::from_path(path).is_ok())
+                .collect(),
+            Err(e) => {
+                logf!(
+                    Error,
+                    "Unable to read image sequence {}: {}",
+                    path.display(),
+                    e
+                );
+                Vec::new()
+            }
+        };
+        paths.sort();
+
+        if paths.is_empty() {
+            logf!(Warning, "Image sequence {} has no images", path.display());
+        }
+
+        Self {
+            path: path.to_path_buf(),
+            paths,
+            position: 0,
+        }
+    }
+}
+
+impl CaptureSource for SequenceSource {
+    fn next_frame(&mut self) -> Result<Frame, CaptureError> {
+        // Images that fail to load are skipped, giving up after trying each of them once
+        for _ in 0..self.paths.len() {
+            let path = &self.paths[self.position];
+            self.position = (self.position + 1) % self.paths.len();
+
+            match image::open(path) {
+                Ok(image) => return Ok(Frame::new(image)),
+                Err(e) => logf!(Warning, "Skipping image {}: {}", path.display(), e),
+            }
+        }
+
+        Err(CaptureError::Unreadable(format!(
+            "{} has no readable images",
+            self.path.display()
+        )))
+    }
+}
+
+/// Generated images for trying out devices without anything on screen
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TestPattern {
+    /// Still color bars
+    Bars,
+    /// A white bar moving across a black screen
+    Sweep,
+    /// The whole screen fading through every hue
+    Cycle,
+}
+
+impl TestPattern {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "bars" => Some(TestPattern::Bars),
+            "sweep" => Some(TestPattern::Sweep),
+            "cycle" => Some(TestPattern::Cycle),
+            _ => None,
+        }
+    }
+}
+
+/// Size of the generated patterns, they are downscaled to a few pixels anyway
+const PATTERN_SIZE: (u32, u32) = (640, 360);
+/// Seconds a moving pattern takes to repeat
+const PATTERN_PERIOD: f32 = 4.0;
+
+pub struct PatternSource {
+    pattern: TestPattern,
+    start: Instant,
+}
+
+impl PatternSource {
+    pub fn new(pattern: TestPattern) -> Self {
+        Self {
+            pattern,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl CaptureSource for PatternSource {
+    fn next_frame(&mut self) -> Result<Frame, CaptureError> {
+        let phase = (self.start.elapsed().as_secs_f32() / PATTERN_PERIOD).fract();
+        let (width, height) = PATTERN_SIZE;
+
+        let image = match self.pattern {
+            TestPattern::Bars => {
+                const BARS: [[u8; 3]; 8] = [
+                    [255, 255, 255],
+                    [255, 255, 0],
+                    [0, 255, 255],
+                    [0, 255, 0],
+                    [255, 0, 255],
+                    [255, 0, 0],
+                    [0, 0, 255],
+                    [0, 0, 0],
+                ];
+                RgbaImage::from_fn(width, height, |x, _| {
+                    let [r, g, b] = BARS[(x * BARS.len() as u32 / width) as usize];
+                    Rgba([r, g, b, 255])
+                })
+            }
+            TestPattern::Sweep => {
+                let bar = (phase * width as f32) as u32;
+                RgbaImage::from_fn(width, height, |x, _| match x.abs_diff(bar) < width / 16 {
+                    true => Rgba([255, 255, 255, 255]),
+                    false => Rgba([0, 0, 0, 255]),
+                })
+            }
+            TestPattern::Cycle => {
+                let [r, g, b] = hue_to_rgb(phase);
+                RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]))
+            }
+        };
+
+        Ok(Frame::new(DynamicImage::ImageRgba8(image)))
+    }
+}
+
+/// Fully saturated color for a hue between 0 and 1
+fn hue_to_rgb(hue: f32) -> [u8; 3] {
+    let channel = |offset: f32| {
+        let distance = ((hue + offset).fract() * 6.0 - 3.0).abs();
+        ((distance - 1.0).clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    [channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn test_directory(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("wootili-view-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn solid(color: [u8; 3]) -> RgbaImage {
+        RgbaImage::from_pixel(2, 2, Rgba([color[0], color[1], color[2], 255]))
+    }
+
+    fn first_pixel(frame: Result<Frame, CaptureError>) -> [u8; 3] {
+        let Rgba([r, g, b, _]) = frame
+            .ok()
+            .unwrap()
+            .image
+            .to_rgba8()
+            .get_pixel(0, 0)
+            .to_owned();
+        [r, g, b]
+    }
+
+    #[test]
+    fn image_source_repeats_the_image() {
+        let directory = test_directory("image");
+        let path = directory.join("red.png");
+        solid([255, 0, 0]).save(&path).unwrap();
+
+        let mut source = ImageSource::new(&path);
+        assert_eq!(first_pixel(source.next_frame()), [255, 0, 0]);
+        assert_eq!(first_pixel(source.next_frame()), [255, 0, 0]);
+
+        let mut missing = ImageSource::new(&directory.join("missing.png"));
+        assert!(matches!(
+            missing.next_frame(),
+            Err(CaptureError::Unreadable(_))
+        ));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn sequence_source_plays_images_in_name_order() {
+        let directory = test_directory("sequence");
+        solid([0, 0, 255]).save(directory.join("2.png")).unwrap();
+        solid([255, 0, 0]).save(directory.join("1.png")).unwrap();
+        fs::write(directory.join("notes.txt"), "not an image").unwrap();
+
+        let mut source = SequenceSource::new(&directory);
+        assert_eq!(first_pixel(source.next_frame()), [255, 0, 0]);
+        assert_eq!(first_pixel(source.next_frame()), [0, 0, 255]);
+        assert_eq!(first_pixel(source.next_frame()), [255, 0, 0]);
+
+        let empty = test_directory("sequence-empty");
+        let mut source = SequenceSource::new(&empty);
+        assert!(matches!(
+            source.next_frame(),
+            Err(CaptureError::Unreadable(_))
+        ));
+
+        fs::remove_dir_all(directory).unwrap();
+        fs::remove_dir_all(empty).unwrap();
+    }
+
+    #[test]
+    fn pattern_source_draws_patterns() {
+        let frame = PatternSource::new(TestPattern::Bars)
+            .next_frame()
+            .ok()
+            .unwrap();
+        let image = frame.image.to_rgba8();
+        assert_eq!(image.dimensions(), PATTERN_SIZE);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(
+            image.get_pixel(PATTERN_SIZE.0 / 2, 0),
+            &Rgba([255, 0, 255, 255])
+        );
+        assert_eq!(
+            image.get_pixel(PATTERN_SIZE.0 - 1, PATTERN_SIZE.1 - 1),
+            &Rgba([0, 0, 0, 255])
+        );
+
+        // The hue changes over time but the whole screen is always a single color
+        let image = PatternSource::new(TestPattern::Cycle)
+            .next_frame()
+            .ok()
+            .unwrap()
+            .image
+            .to_rgba8();
+        let color = image.get_pixel(0, 0);
+        assert!(image.pixels().all(|pixel| pixel == color));
+    }
+}
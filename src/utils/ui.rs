@@ -1,19 +1,30 @@
 use eframe::egui::{self, SelectableLabel, Ui};
 use egui_notify::Toasts;
-use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use image::{DynamicImage, GenericImageView};
 use scorched::{log_this, logf, LogData, LogImportance};
 use std::{sync::atomic::Ordering, time::Duration};
+use xcap::Monitor;
 
 use crate::{
     save_config_option, utils::capture::CAPTURE_SETTINGS, utils::paths, wooting, ConfigChange,
 };
 
-use super::capture;
+use super::{
+    calibration::{Calibration, CalibrationPreview},
+    capture,
+    downscale::DownscaleMethod,
+    firmware::{FirmwareFeature, FirmwareStatus},
+    monitor::{self, MonitorIdentity},
+    pipeline::{self, StageConfig, StageKind},
+    source::CaptureError,
+    wooting::DeviceInfo,
+};
 
 pub fn downscale_label(
     ui: &mut Ui,
-    current: &mut FilterType,
-    new: FilterType,
+    device_index: usize,
+    current: &mut DownscaleMethod,
+    new: DownscaleMethod,
     label: &str,
     hover_text: &str,
     toasts: &mut Toasts,
@@ -23,13 +34,232 @@ pub fn downscale_label(
         .on_hover_text(hover_text)
         .clicked()
     {
-        save_config_option(ConfigChange::DownscaleMethod(new), toasts);
-        CAPTURE_SETTINGS.write().unwrap().downscale_method = new;
+        save_config_option(ConfigChange::DownscaleMethod(device_index, new), toasts);
+        CAPTURE_SETTINGS.write().unwrap().devices[device_index].downscale_method = new;
         capture::CAPTURE_SETTINGS_RELOAD.store(true, Ordering::Relaxed);
         *current = new;
     }
 }
 
+/// Checkbox that turns the first stage that `matches` on and off, left out when the device has no such stage
+pub fn stage_checkbox(
+    ui: &mut Ui,
+    stages: &mut [StageConfig],
+    matches: fn(&StageKind) -> bool,
+    label: &str,
+    hover_text: &str,
+) -> bool {
+    match pipeline::stage_enabled(stages, matches) {
+        Some(enabled) => ui
+            .checkbox(enabled, label)
+            .on_hover_text(hover_text)
+            .changed(),
+        None => false,
+    }
+}
+
+/// Slider for the parameter of the first stage it can be found in, with a `neutral` value the stage is only enabled
+/// while the parameter is away from it
+pub fn stage_slider(
+    ui: &mut Ui,
+    stages: &mut [StageConfig],
+    value: fn(&mut StageKind) -> Option<&mut f32>,
+    range: std::ops::RangeInclusive<f32>,
+    label: &str,
+    hover_text: &str,
+    neutral: Option<f32>,
+) -> bool {
+    let Some((value, enabled)) = pipeline::stage_value(stages, value) else {
+        return false;
+    };
+
+    let changed = ui
+        .add(egui::Slider::new(value, range).text(label))
+        .on_hover_text(hover_text)
+        .changed();
+    if let (true, Some(neutral)) = (changed, neutral) {
+        *enabled = *value != neutral;
+    }
+
+    changed
+}
+
+/// Lists the color stages of a device to turn them on and off and change their order, true when anything changed
+pub fn color_stages_editor(ui: &mut Ui, stages: &mut [StageConfig]) -> bool {
+    let mut changed = false;
+    let mut swap = None;
+    let count = stages.len();
+
+    for (index, stage) in stages.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            changed |= ui
+                .checkbox(&mut stage.enabled, stage.stage.name())
+                .changed();
+            if ui
+                .add_enabled(index > 0, egui::Button::new("⏶"))
+                .on_hover_text("Run this stage earlier")
+                .clicked()
+            {
+                swap = Some((index - 1, index));
+            }
+            if ui
+                .add_enabled(index + 1 < count, egui::Button::new("⏷"))
+                .on_hover_text("Run this stage later")
+                .clicked()
+            {
+                swap = Some((index, index + 1));
+            }
+        });
+    }
+
+    if let Some((a, b)) = swap {
+        stages.swap(a, b);
+        changed = true;
+    }
+
+    changed
+}
+
+/// A step of the calibration wizard, the user picks which of the reference colors is shown
+struct CalibrationStep {
+    title: &'static str,
+    instructions: &'static str,
+    references: &'static [(&'static str, [u8; 3])],
+}
+
+const CALIBRATION_STEPS: [CalibrationStep; 3] = [
+    CalibrationStep {
+        title: "White Point",
+        instructions: "Move the white point until white on the keyboard looks the same as white on the screen.",
+        references: &[("White", [255, 255, 255])],
+    },
+    CalibrationStep {
+        title: "Channel Gain",
+        instructions: "Change the gain of each channel until the colors on the keyboard match the screen, starting with the color that looks the most off.",
+        references: &[
+            ("Red", [255, 0, 0]),
+            ("Green", [0, 255, 0]),
+            ("Blue", [0, 0, 255]),
+            ("Yellow", [255, 255, 0]),
+            ("Cyan", [0, 255, 255]),
+            ("Magenta", [255, 0, 255]),
+        ],
+    },
+    CalibrationStep {
+        title: "Gamma",
+        instructions: "Change the gamma of each channel until the grays on the keyboard have the same brightness and tint as the screen.",
+        references: &[
+            ("Dark Gray", [64, 64, 64]),
+            ("Gray", [128, 128, 128]),
+            ("Light Gray", [192, 192, 192]),
+        ],
+    },
+];
+
+/// Calibration of a device being changed in the calibration wizard
+pub struct CalibrationWizard {
+    pub device: usize,
+    pub calibration: Calibration,
+    step: usize,
+    reference: usize,
+}
+
+pub enum WizardResult {
+    Open,
+    Cancelled,
+    Finished,
+}
+
+impl CalibrationWizard {
+    pub fn new(device: usize, calibration: Calibration) -> Self {
+        Self {
+            device,
+            calibration,
+            step: 0,
+            reference: 0,
+        }
+    }
+
+    /// What the device shows while the wizard is open
+    pub fn preview(&self) -> CalibrationPreview {
+        CalibrationPreview {
+            device: self.device,
+            color: CALIBRATION_STEPS[self.step].references[self.reference].1,
+            calibration: self.calibration,
+        }
+    }
+}
+
+/// Shows the same reference color on the screen and the keyboard while the calibration is adjusted step by step
+pub fn calibration_wizard(ctx: &egui::Context, wizard: &mut CalibrationWizard) -> WizardResult {
+    let mut result = WizardResult::Open;
+
+    egui::Window::new("Color Calibration").collapsible(false).resizable(false).show(ctx, |ui| {
+        let CalibrationStep {
+            title,
+            instructions,
+            references,
+        } = &CALIBRATION_STEPS[wizard.step];
+        ui.heading(format!("Step {} of {}: {}", wizard.step + 1, CALIBRATION_STEPS.len(), title));
+        ui.label(*instructions);
+        ui.horizontal_wrapped(|ui| {
+            for (index, (label, _)) in references.iter().enumerate() {
+                ui.selectable_value(&mut wizard.reference, index, *label);
+            }
+        });
+
+        let [r, g, b] = references[wizard.reference].1;
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width().max(240.0), 120.0), egui::Sense::hover());
+        ui.painter().rect_filled(rect, 4.0, egui::Color32::from_rgb(r, g, b));
+
+        let calibration = &mut wizard.calibration;
+        match wizard.step {
+            0 => {
+                ui.add(egui::Slider::new(&mut calibration.white_point, 3000..=9000).text("White Point").suffix("K")).on_hover_text("Lower is warmer and higher is cooler, 6500K leaves white alone");
+            }
+            1 => {
+                for (channel, label) in ["Red Gain", "Green Gain", "Blue Gain"].into_iter().enumerate() {
+                    ui.add(egui::Slider::new(&mut calibration.gain[channel], 0.2..=1.5).text(label));
+                }
+            }
+            _ => {
+                for (channel, label) in ["Red Gamma", "Green Gamma", "Blue Gamma"].into_iter().enumerate() {
+                    ui.add(egui::Slider::new(&mut calibration.gamma[channel], 0.4..=2.5).text(label)).on_hover_text("Above 1 darkens the midtones of the channel and below 1 brightens them");
+                }
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Red Shift Preset").on_hover_text("Starts from the correction the Red Shift Fix used, for boards with stock keycaps or switches like the Geon Raptor HE").clicked() {
+                *calibration = Calibration::RED_SHIFT;
+            }
+            if ui.button("Reset").on_hover_text("Starts over without any correction").clicked() {
+                *calibration = Calibration::default();
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Cancel").on_hover_text("Closes the wizard and keeps the old calibration").clicked() {
+                result = WizardResult::Cancelled;
+            }
+            if ui.add_enabled(wizard.step > 0, egui::Button::new("Back")).clicked() {
+                wizard.step -= 1;
+                wizard.reference = 0;
+            }
+            if wizard.step + 1 < CALIBRATION_STEPS.len() {
+                if ui.button("Next").clicked() {
+                    wizard.step += 1;
+                    wizard.reference = 0;
+                }
+            } else if ui.button("Finish").on_hover_text("Saves the calibration for this device").clicked() {
+                result = WizardResult::Finished;
+            }
+        });
+    });
+
+    result
+}
+
 pub fn clean_logs_button(ui: &mut Ui, toasts: &mut Toasts) {
     if ui
         .button("Clean Logs")
@@ -56,6 +286,94 @@ pub fn clean_logs_button(ui: &mut Ui, toasts: &mut Toasts) {
     }
 }
 
+/// Texture of the thumbnail shown by the crop editor, kept between frames so it is only uploaded again when the
+/// thumbnail changes
+pub struct CropPreview {
+    texture: egui::TextureHandle,
+    thumbnail: DynamicImage,
+}
+
+/// Thumbnail of the screen with the capture area drawn over it, dragging on the thumbnail selects a new area.
+/// Returns true once a drag has finished.
+pub fn crop_editor(
+    ui: &mut Ui,
+    thumbnail: Option<&DynamicImage>,
+    preview: &mut Option<CropPreview>,
+    crop: &mut (f32, f32, f32, f32),
+) -> bool {
+    let Some(thumbnail) = thumbnail else {
+        ui.label("Waiting for a screen capture...");
+        return false;
+    };
+
+    let size = [thumbnail.width() as usize, thumbnail.height() as usize];
+    let image = || egui::ColorImage::from_rgba_unmultiplied(size, thumbnail.to_rgba8().as_raw());
+    let preview = preview.get_or_insert_with(|| CropPreview {
+        texture: ui
+            .ctx()
+            .load_texture("crop_thumbnail", image(), egui::TextureOptions::LINEAR),
+        thumbnail: thumbnail.clone(),
+    });
+    if preview.thumbnail != *thumbnail {
+        preview.texture.set(image(), egui::TextureOptions::LINEAR);
+        preview.thumbnail = thumbnail.clone();
+    }
+    let texture = &preview.texture;
+
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(size[0] as f32, size[1] as f32),
+        egui::Sense::drag(),
+    );
+    let rect = response.rect;
+    painter.image(
+        texture.id(),
+        rect,
+        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+        egui::Color32::WHITE,
+    );
+
+    let to_fraction = |pos: egui::Pos2| {
+        ((pos - rect.min) / rect.size()).clamp(egui::Vec2::ZERO, egui::Vec2::splat(1.0))
+    };
+    let start_id = response.id.with("crop_start");
+
+    if response.drag_started() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            ui.data_mut(|data| data.insert_temp(start_id, to_fraction(pos)));
+        }
+    }
+    if response.dragged() {
+        let start = ui.data(|data| data.get_temp::<egui::Vec2>(start_id));
+        if let (Some(start), Some(pos)) = (start, response.interact_pointer_pos()) {
+            let end = to_fraction(pos);
+            let (min, max) = (start.min(end), start.max(end));
+
+            // Too small to sample anything useful
+            *crop = (
+                min.x,
+                min.y,
+                (max.x - min.x).max(0.02),
+                (max.y - min.y).max(0.02),
+            );
+        }
+    }
+
+    let crop_rect = egui::Rect::from_min_size(
+        rect.min + egui::vec2(crop.0, crop.1) * rect.size(),
+        egui::vec2(crop.2, crop.3) * rect.size(),
+    );
+    painter.rect_stroke(
+        crop_rect,
+        0.0,
+        egui::Stroke::new(2.0, egui::Color32::RED),
+        egui::StrokeKind::Outside,
+    );
+
+    response
+        .on_hover_text("Drag over the screen to select the area that is shown on the device")
+        .drag_stopped()
+}
+
 pub fn rgb_preview(ui: &mut egui::Ui, frame_rgb_size: (u32, u32), resized_capture: DynamicImage) {
     if frame_rgb_size == resized_capture.dimensions() {
         ui.heading("Preview Lighting");
@@ -78,43 +396,186 @@ pub fn rgb_preview(ui: &mut egui::Ui, frame_rgb_size: (u32, u32), resized_captur
     }
 }
 
-pub fn display_device_info(
-    ui: &mut egui::Ui,
-    toasts: &mut Toasts,
-    device_name: &mut String,
-    device_creation: &mut String,
-    device_version: &mut String,
-    init: &mut bool,
-    frame_rgb_size: (u32, u32),
-) {
+/// Outline of the capture with the part kept after removing black bars filled in, nothing when there are no bars
+pub fn letterbox_preview(ui: &mut Ui, letterbox: (f32, f32, f32, f32)) {
+    if letterbox == (0.0, 0.0, 1.0, 1.0) {
+        return;
+    }
+
+    ui.horizontal(|ui| {
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(48.0, 27.0), egui::Sense::hover());
+        let kept = egui::Rect::from_min_size(
+            rect.min + egui::vec2(letterbox.0, letterbox.1) * rect.size(),
+            egui::vec2(letterbox.2, letterbox.3) * rect.size(),
+        );
+        ui.painter().rect_filled(rect, 1.0, egui::Color32::BLACK);
+        ui.painter().rect_filled(kept, 1.0, egui::Color32::GRAY);
+        ui.painter().rect_stroke(
+            rect,
+            1.0,
+            egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
+            egui::StrokeKind::Outside,
+        );
+        response.on_hover_text(
+            "Black bars around the capture are cropped away before it is shown on the device",
+        );
+
+        ui.label(format!(
+            "Black Bars Removed, showing {}% x {}%",
+            (letterbox.2 * 100.0).round(),
+            (letterbox.3 * 100.0).round()
+        ));
+    });
+
+    ui.separator();
+}
+
+/// The connected screens shown by the screen dropdown, kept between frames because listing them is slow. They are
+/// listed again when the dropdown opens or after `refresh`.
+#[derive(Default)]
+pub struct ScreenList {
+    screens: Option<Vec<(Monitor, MonitorIdentity)>>,
+    open: bool,
+}
+
+impl ScreenList {
+    /// Lists the screens again the next time they are needed
+    pub fn refresh(&mut self) {
+        self.screens = None;
+    }
+
+    fn screens(&mut self) -> &[(Monitor, MonitorIdentity)] {
+        self.screens
+            .get_or_insert_with(|| monitor::monitors().unwrap_or_default())
+    }
+}
+
+/// Dropdown of the connected screens by name and resolution, returns true when another screen was picked.
+/// A saved screen that is not connected stays selected and shows the screen captured in its place.
+pub fn screen_dropdown(
+    ui: &mut Ui,
+    screens: &mut ScreenList,
+    current: &mut Option<MonitorIdentity>,
+) -> bool {
+    let selected_text = match current.as_ref() {
+        Some(saved) => {
+            let list = screens.screens();
+            // The same lookup as the capture, so this shows the screen that is actually captured
+            match monitor::find_monitor(list, Some(saved)).map(|index| &list[index].1) {
+                Some(found) if found.label() == saved.label() => saved.label(),
+                Some(found) => format!("{} (Missing, using {})", saved.label(), found.label()),
+                None => format!("{} (Missing)", saved.label()),
+            }
+        }
+        None => "Primary Screen".to_string(),
+    };
+
+    let mut changed = false;
+    let dropdown = egui::ComboBox::from_label("Screen")
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            if !screens.open {
+                screens.refresh();
+                screens.open = true;
+            }
+
+            changed |= ui
+                .selectable_value(current, None, "Primary Screen")
+                .on_hover_text("Follows whichever screen is the primary one")
+                .changed();
+            for (_, identity) in screens.screens() {
+                changed |= ui
+                    .selectable_value(current, Some(identity.clone()), identity.label())
+                    .changed();
+            }
+        });
+    dropdown.response.on_hover_text("Select the screen to capture, a missing screen is replaced by the closest match until it is back");
+    screens.open = dropdown.inner.is_some();
+
+    changed
+}
+
+/// Shows why a device is not being captured, nothing while capturing works
+pub fn capture_status_label(ui: &mut Ui, status: Option<&CaptureError>) {
+    if let Some(error) = status {
+        ui.colored_label(
+            ui.visuals().error_fg_color,
+            format!("Capture Paused: {}", error),
+        )
+        .on_hover_text(
+            "Capturing is tried again automatically, waiting a little longer after each failure",
+        );
+    }
+}
+
+pub fn device_info_header(ui: &mut egui::Ui, toasts: &mut Toasts, init: &mut bool) {
     ui.horizontal(|ui| {
         ui.heading("Device Info");
-        if ui.add(egui::Button::new("Refresh")).on_hover_text("Refreshes the device info, devices should instantly be picked up automatically, but if you have multiple wooting devices plugged in or you want to force refresh you can with this.").clicked() {
+        if ui.add(egui::Button::new("Refresh")).on_hover_text("Refreshes the device info, devices should instantly be picked up automatically, but if you plugged in another wooting device or you want to force refresh you can with this.").clicked() {
             toasts
                 .info("Refreshing Device Info")
                 .duration(Some(std::time::Duration::from_secs(1)));
 
             capture::CAPTURE_LOCK.store(true, Ordering::Relaxed);
             wooting::reconnect_device();
+            wooting::refresh_device_info();
             capture::CAPTURE_LOCK.store(false, Ordering::Relaxed);
 
-            *device_name = wooting::get_device_name();
-            *device_creation = wooting::get_device_creation(0);
             *init = true;
         }
     });
-    ui.add(egui::Label::new(format!("Name: {}", device_name,)));
-    ui.label(format!("Creation: {}", device_creation)).on_hover_text("This is the manufacture date found on your device's board; this may differ from when you received the device");
+}
+
+/// Renders the cached info of a device, `None` for a device that is not connected
+pub fn display_device_info(ui: &mut egui::Ui, device_info: Option<&DeviceInfo>) {
+    let Some(device_info) = device_info else {
+        ui.add(egui::Label::new("Name: N/A"));
+        display_lighting_dimensions(ui, (0, 0));
+        return;
+    };
+
+    ui.add(egui::Label::new(format!("Name: {}", device_info.model)));
+    ui.label(format!("Type: {}", device_info.meta.description()))
+        .on_hover_text("The type and layout reported by the device");
+    ui.label(format!(
+        "Serial: {}",
+        device_info.serial.as_deref().unwrap_or("N/A")
+    ));
+    ui.label(format!("Creation: {}", device_info.creation_description())).on_hover_text("This is the manufacture date found on your device's board; this may differ from when you received the device");
     ui.horizontal(|ui| {
-        ui.label(format!("Firmware Version: {}", device_version));
-        /*
-        if device_version.1 {
-            ui.label(" (Unsupported)").highlight().on_hover_text("This firmware version is unsupported, please downgrade to a supported version for the time being (<=2.8.0)");
+        let firmware = device_info
+            .firmware
+            .map(|firmware| firmware.to_string())
+            .unwrap_or("N/A".to_string());
+        ui.label(format!("Firmware Version: {}", firmware));
+        if let Some(compatibility) = device_info.compatibility.as_ref() {
+            let hover_text = compatibility.note.clone().unwrap_or(match compatibility.status {
+                FirmwareStatus::KnownGood => "This firmware version is known to work".to_string(),
+                FirmwareStatus::KnownBroken => "This firmware version is known to have issues, please change to a supported version".to_string(),
+                FirmwareStatus::Untested => "This firmware version has not been tested yet, please report any issues you run into".to_string(),
+            });
+            match compatibility.status {
+                FirmwareStatus::KnownGood => ui.label(format!("({})", compatibility.description())),
+                _ => ui.label(format!("({})", compatibility.description())).highlight(),
+            }
+            .on_hover_text(hover_text);
         }
-        */
     });
+    if let Some(compatibility) = device_info.compatibility.as_ref() {
+        if !compatibility.missing_features.is_empty() {
+            let features: Vec<&str> = compatibility
+                .missing_features
+                .iter()
+                .map(|feature| match feature {
+                    FirmwareFeature::Lighting => "Lighting",
+                    FirmwareFeature::SerialReport => "Serial",
+                })
+                .collect();
+            ui.label(format!("Disabled: {}", features.join(", "))).on_hover_text("These features are not supported by the firmware of this device and have been disabled");
+        }
+    }
 
-    display_lighting_dimensions(ui, frame_rgb_size);
+    display_lighting_dimensions(ui, device_info.rgb_size);
 }
 
 fn display_lighting_dimensions(ui: &mut egui::Ui, frame_rgb_size: (u32, u32)) {
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use image::DynamicImage;
+use scorched::{logf, LogData, LogImportance};
+use xcap::Window;
+
+use super::source::{CaptureError, CaptureSource, Frame};
+
+/// Time between searches for a window that could not be found
+const SEARCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Finds the first window whose title or app name contains `query`, ignoring case and minimized windows
+pub fn find_window(query: &str) -> Option<Window> {
+    let query = query.to_lowercase();
+
+    Window::all().ok()?.into_iter().find(|window| {
+        !window.is_minimized().unwrap_or(true)
+            && [window.title(), window.app_name()]
+                .into_iter()
+                .filter_map(|name| name.ok())
+                .any(|name| name.to_lowercase().contains(&query))
+    })
+}
+
+/// Titles of the windows that can be captured, used to suggest windows in the UI
+pub fn window_titles() -> Vec<String> {
+    let mut titles: Vec<String> = Window::all()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|window| !window.is_minimized().unwrap_or(true))
+        .filter_map(|window| window.title().ok())
+        .filter(|title| !title.is_empty())
+        .collect();
+    titles.sort();
+    titles.dedup();
+
+    titles
+}
+
+/// Keeps track of the window matching a query across frames
+pub struct WindowTracker {
+    pub query: String,
+    window: Option<Window>,
+    last_search: Option<Instant>,
+    monitor: Option<String>,
+}
+
+impl WindowTracker {
+    pub fn new(query: &str) -> Self {
+        Self {
+            query: query.to_string(),
+            window: None,
+            last_search: None,
+            monitor: None,
+        }
+    }
+
+    fn search(&mut self) {
+        if self
+            .last_search
+            .is_some_and(|last_search| last_search.elapsed() < SEARCH_INTERVAL)
+        {
+            return;
+        }
+        self.last_search = Some(Instant::now());
+
+        self.window = find_window(&self.query);
+        if let Some(window) = self.window.as_ref() {
+            logf!(
+                Info,
+                "Capturing window {}",
+                window.title().unwrap_or(self.query.clone())
+            );
+        }
+    }
+}
+
+impl CaptureSource for WindowTracker {
+    /// Captures the window wherever it is, fails while it is closed or minimized so the caller can fall back to the
+    /// screen
+    fn next_frame(&mut self) -> Result<Frame, CaptureError> {
+        if self.window.is_none() {
+            self.search();
+        }
+
+        let not_found = CaptureError::WindowNotFound(self.query.clone());
+        let Some(window) = self.window.as_ref() else {
+            return Err(not_found);
+        };
+        let capture = match window.is_minimized() {
+            Ok(false) => window.capture_image().ok(),
+            _ => None,
+        };
+
+        let Some(capture) = capture else {
+            logf!(
+                Warning,
+                "Lost window {}, capturing the screen until it is back",
+                self.query
+            );
+            self.window = None;
+            self.monitor = None;
+            return Err(not_found);
+        };
+
+        // Window capture follows the window by itself, the monitor is only tracked for the logs
+        let monitor = window
+            .current_monitor()
+            .and_then(|monitor| monitor.name())
+            .ok();
+        if monitor != self.monitor {
+            logf!(
+                Info,
+                "Window {} is on monitor {}",
+                self.query,
+                monitor.as_deref().unwrap_or("N/A")
+            );
+            self.monitor = monitor;
+        }
+
+        Ok(Frame::new(DynamicImage::ImageRgba8(capture)))
+    }
+}
@@ -1,159 +1,563 @@
-use std::ffi::CStr;
+use std::{
+    ffi::{c_char, c_int, c_ushort, c_void, CStr},
+    sync::{Mutex, RwLock},
+};
 
 use image::GenericImageView;
 use scorched::{logf, LogData, LogExpect, LogImportance};
 use wooting_rgb_sys as wooting;
 
-pub fn get_rgb_size() -> Option<(u32, u32)> {
-    let model_name = get_device_name();
+use super::{
+    definitions::{self, DeviceDefinition, PhysicalLayout, Quirk},
+    device::{KeyFrame, RgbDevice, MATRIX_COLUMNS, MATRIX_ROWS},
+    firmware::{self, FirmwareCompatibility, FirmwareFeature, FirmwareStatus, FirmwareVersion},
+    pipeline::{ColorPipeline, Key},
+};
 
-    match model_name.as_str() {
-        //TODO: Verify these sizes for the one two and uwu
-        "Wooting One" => Some((17, 6)),
-        "Wooting Two" | "Wooting Two LE" | "Wooting Two HE" | "Wooting Two HE (ARM)" => {
-            Some((17, 6))
+/// The SDK keeps the selected device in global state, every call into it has to hold this lock
+static SDK_LOCK: Mutex<()> = Mutex::new(());
+static DEVICE_INFO: RwLock<Vec<DeviceInfo>> = RwLock::new(Vec::new());
+
+/// Feature reports of the Wooting firmware
+const FEATURE_GET_VERSION: u8 = 1;
+const FEATURE_GET_SERIAL: u8 = 3;
+/// Attempts at a feature report before giving up, the first response after connecting is sometimes empty
+const FEATURE_ATTEMPTS: u8 = 4;
+
+/// Vendor ids of the Wooting keyboards, the older boards use the id of their microcontroller vendor
+const WOOTING_VIDS: [c_ushort; 2] = [0x03eb, 0x31e3];
+/// Usage page of the interface the SDK talks to
+const CFG_USAGE_PAGE: c_ushort = 0x1337;
+
+/// Start of `hid_device_info` from the public hidapi header, newer hidapi versions only add fields after `next`
+#[repr(C)]
+struct HidDeviceInfo {
+    path: *const c_char,
+    vendor_id: c_ushort,
+    product_id: c_ushort,
+    serial_number: *const c_void,
+    release_number: c_ushort,
+    manufacturer_string: *const c_void,
+    product_string: *const c_void,
+    usage_page: c_ushort,
+    usage: c_ushort,
+    interface_number: c_int,
+    next: *mut HidDeviceInfo,
+}
+
+// hidapi is linked in by wooting-rgb-sys, the SDK is built on top of it
+extern "C" {
+    fn hid_enumerate(vendor_id: c_ushort, product_id: c_ushort) -> *mut HidDeviceInfo;
+    fn hid_free_enumeration(devices: *mut HidDeviceInfo);
+}
+
+/// A keyboard managed by the Wooting SDK, identified by its index in the SDK device list
+pub struct WootingDevice {
+    pub index: u8,
+    /// Keys set since the last flush, they are sent together so the device is only selected once per frame
+    pending: Vec<(u8, u8, (u8, u8, u8))>,
+}
+
+impl WootingDevice {
+    pub fn new(index: u8) -> Self {
+        Self {
+            index,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl RgbDevice for WootingDevice {
+    fn connect(&mut self) -> bool {
+        let _sdk = SDK_LOCK.lock().unwrap();
+        unsafe {
+            // Finds the keyboards if the SDK is not connected yet
+            wooting::wooting_rgb_kbd_connected() && wooting::wooting_usb_select_device(self.index)
+        }
+    }
+
+    /// Resets the lighting of this device only, the connection stays open for the other devices
+    fn disconnect(&mut self) {
+        self.pending.clear();
+        let _sdk = SDK_LOCK.lock().unwrap();
+        unsafe {
+            if wooting::wooting_usb_select_device(self.index) {
+                wooting::wooting_rgb_reset_rgb();
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        device_info()
+            .get(self.index as usize)
+            .map(|info| info.model.clone())
+            .unwrap_or("N/A".to_string())
+    }
+
+    fn rgb_size(&self) -> Option<(u32, u32)> {
+        Some(
+            device_info()
+                .get(self.index as usize)
+                .map(|info| info.rgb_size)
+                .unwrap_or((0, 0)),
+        )
+    }
+
+    fn first_row(&self) -> u8 {
+        get_first_row(&self.name())
+    }
+
+    fn set_key(&mut self, row: u8, column: u8, color: (u8, u8, u8)) {
+        self.pending.push((row, column, color));
+    }
+
+    fn flush(&mut self) -> bool {
+        let _sdk = SDK_LOCK.lock().unwrap();
+        unsafe {
+            if !wooting::wooting_usb_select_device(self.index) {
+                self.pending.clear();
+                return false;
+            }
+
+            for (row, column, (red, green, blue)) in self.pending.drain(..) {
+                wooting::wooting_rgb_array_set_single(row, column, red, green, blue);
+            }
+            wooting::wooting_rgb_array_update_keyboard()
+        }
+    }
+}
+
+/// Returns a device for every keyboard in the cached device info, always at least one so a device plugged in later is picked up
+pub fn get_devices() -> Vec<WootingDevice> {
+    let count = (device_info().len() as u8).max(1);
+
+    (0..count).map(WootingDevice::new).collect()
+}
+
+/// Sorted HID paths of the plugged in keyboards, only used to notice that a device was plugged in or removed. The
+/// paths say nothing about which SDK index a device has, devices are told apart by their serial instead. This only
+/// lists the devices and never opens them, the SDK connection is left alone.
+pub fn device_paths() -> Vec<String> {
+    let mut paths = Vec::new();
+
+    for vendor_id in WOOTING_VIDS {
+        unsafe {
+            let devices = hid_enumerate(vendor_id, 0);
+            let mut device = devices;
+            while let Some(info) = device.as_ref() {
+                if info.usage_page == CFG_USAGE_PAGE && !info.path.is_null() {
+                    paths.push(CStr::from_ptr(info.path).to_string_lossy().to_string());
+                }
+                device = info.next;
+            }
+            hid_free_enumeration(devices);
         }
-        "Wooting 60HE" | "Wooting 60HE (ARM)" => Some((14, 5)),
-        "Wooting UwU RGB" => Some((6, 4)),
-        "Wooting UwU" => {
+    }
+
+    paths.sort();
+    paths
+}
+
+/// Sets a callback the SDK calls when writing to a device fails because it was unplugged
+pub fn set_disconnected_callback(callback: unsafe extern "C" fn()) {
+    let _sdk = SDK_LOCK.lock().unwrap();
+    unsafe {
+        wooting::wooting_rgb_set_disconnected_cb(Some(callback));
+    }
+}
+
+/// Looks up the lighting matrix size of a model, falling back to what the device reports when there is no definition for it
+fn resolve_rgb_size(model_name: &str, meta: DeviceMeta) -> Option<(u32, u32)> {
+    let definition = definitions::find_definition(model_name).or_else(|| {
+        let definition = derive_definition(model_name, meta)?;
+        logf!(
+            Warning,
+            "No device definition for {}, using the {}x{} lighting matrix reported by the device",
+            model_name,
+            definition.rgb_size.0,
+            definition.rgb_size.1
+        );
+        definitions::add_definition(definition.clone());
+        Some(definition)
+    });
+
+    match definition {
+        Some(definition) if definition.quirks.contains(&Quirk::NoLighting) => {
             logf!(
                 Warning,
-                "Standard Wooting UwU device does not have any RGB lights, returning (0, 0)"
+                "{} device does not have any RGB lights, returning (0, 0)",
+                model_name
             );
             Some((0, 0))
         }
-        _ => {
+        Some(definition) => Some(definition.rgb_size),
+        None => {
             logf!(Error, "Unsupported device model: {}", model_name);
             None
         }
     }
 }
 
-pub fn get_device_name() -> String {
-    unsafe {
-        wooting::wooting_usb_disconnect(false);
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        wooting::wooting_usb_find_keyboard();
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DeviceType {
+    Tenkeyless,
+    FullSize,
+    SixtyPercent,
+    Keypad,
+    EightyPercent,
+    Unknown,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DeviceLayout {
+    Ansi,
+    Iso,
+    Unknown,
+}
+
+/// Lighting details the SDK reports for a device
+#[derive(Clone, Copy)]
+pub struct DeviceMeta {
+    pub max_rows: u8,
+    pub max_columns: u8,
+    pub led_index_max: u8,
+    pub device_type: DeviceType,
+    pub layout: DeviceLayout,
+}
 
-        let wooting_usb_meta = *wooting::wooting_usb_get_meta();
-        let model = CStr::from_ptr(wooting_usb_meta.model);
+impl DeviceMeta {
+    /// Short human readable form, for example "60% ISO"
+    pub fn description(&self) -> String {
+        let device_type = match self.device_type {
+            DeviceType::Tenkeyless => "Tenkeyless",
+            DeviceType::FullSize => "Full Size",
+            DeviceType::SixtyPercent => "60%",
+            DeviceType::Keypad => "Keypad",
+            DeviceType::EightyPercent => "80%",
+            DeviceType::Unknown => "Unknown",
+        };
 
-        model
-            .to_str()
-            .log_expect(LogImportance::Error, "Failed to convert device name to str")
-            .to_string()
+        match self.layout {
+            DeviceLayout::Ansi => format!("{} ANSI", device_type),
+            DeviceLayout::Iso => format!("{} ISO", device_type),
+            DeviceLayout::Unknown => device_type.to_string(),
+        }
     }
 }
 
-pub fn get_device_creation(depth: u8) -> String {
-    unsafe {
-        wooting::wooting_usb_disconnect(false);
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        wooting::wooting_usb_find_keyboard();
+fn device_meta(wooting_usb_meta: &wooting::WOOTING_USB_META) -> DeviceMeta {
+    DeviceMeta {
+        max_rows: wooting_usb_meta.max_rows,
+        max_columns: wooting_usb_meta.max_columns,
+        led_index_max: wooting_usb_meta.led_index_max,
+        device_type: match wooting_usb_meta.device_type {
+            wooting::WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD_TKL => DeviceType::Tenkeyless,
+            wooting::WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD => DeviceType::FullSize,
+            wooting::WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD_60 => DeviceType::SixtyPercent,
+            wooting::WOOTING_DEVICE_TYPE_DEVICE_KEYPAD_3KEY => DeviceType::Keypad,
+            wooting::WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD_80 => DeviceType::EightyPercent,
+            _ => DeviceType::Unknown,
+        },
+        layout: match wooting_usb_meta.layout {
+            wooting::WOOTING_DEVICE_LAYOUT_LAYOUT_ANSI => DeviceLayout::Ansi,
+            wooting::WOOTING_DEVICE_LAYOUT_LAYOUT_ISO => DeviceLayout::Iso,
+            _ => DeviceLayout::Unknown,
+        },
+    }
+}
 
-        let len = u8::MAX as usize + 3;
-        let mut buff = vec![0u8; len];
-        wooting::wooting_usb_send_feature_with_response(buff.as_mut_ptr(), len, 3, 0, 0, 0, 0);
+/// Builds a definition for a model without one from what the device reports, returns None if the device reports no lighting matrix
+fn derive_definition(model_name: &str, meta: DeviceMeta) -> Option<DeviceDefinition> {
+    let quirks = match meta.led_index_max {
+        0 => vec![Quirk::NoLighting],
+        _ => Vec::new(),
+    };
+
+    if quirks.is_empty() && (meta.max_rows == 0 || meta.max_columns == 0) {
+        return None;
+    }
 
-        let year: u16 = 2000 + buff[7] as u16;
-        let week = buff[8];
+    // 60% boards keep the matrix of the bigger boards but have no function row
+    let first_row = match meta.device_type {
+        DeviceType::SixtyPercent => 1,
+        _ => 0,
+    };
 
-        if year == 2000 && week == 0 {
-            logf!(
-                Warning,
-                "Failed to get device creation date retry {}",
-                depth
-            );
-            if depth < 3 {
-                return get_device_creation(depth + 1);
-            }
-            "N/A".to_string()
-        } else {
-            format!("Week {} of {}", week, year)
+    Some(DeviceDefinition {
+        name: model_name.to_string(),
+        models: vec![model_name.to_string()],
+        rgb_size: (
+            meta.max_columns as u32,
+            meta.max_rows.saturating_sub(first_row) as u32,
+        ),
+        first_row,
+        layout: match meta.device_type {
+            DeviceType::SixtyPercent => Some(PhysicalLayout::SixtyPercent),
+            DeviceType::Tenkeyless => Some(PhysicalLayout::Tenkeyless),
+            DeviceType::FullSize => Some(PhysicalLayout::FullSize),
+            _ => None,
+        },
+        led_mask: Vec::new(),
+        quirks,
+        firmware: Vec::new(),
+    })
+}
+
+/// Matrix row lit by the first row of a downscaled frame, 60% boards have no function row so they start on the number row
+pub fn get_first_row(model_name: &str) -> u8 {
+    definitions::find_definition(model_name)
+        .map(|definition| definition.first_row)
+        .unwrap_or(1)
+}
+
+/// Everything the app needs to know about a device, read in a single session by `refresh_device_info`
+#[derive(Clone)]
+pub struct DeviceInfo {
+    pub model: String,
+    /// Tells the device apart from the others when the SDK index changes. None when the firmware does not report it
+    pub serial: Option<String>,
+    /// Manufacture date as (week, year)
+    pub creation: Option<(u8, u16)>,
+    pub firmware: Option<FirmwareVersion>,
+    /// None when the firmware version could not be read
+    pub compatibility: Option<FirmwareCompatibility>,
+    pub meta: DeviceMeta,
+    pub rgb_size: (u32, u32),
+}
+
+impl DeviceInfo {
+    pub fn creation_description(&self) -> String {
+        match self.creation {
+            Some((week, year)) => format!("Week {} of {}", week, year),
+            None => "N/A".to_string(),
         }
     }
 }
 
-pub fn get_device_version() -> String {
-    unsafe {
-        wooting::wooting_usb_disconnect(false);
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        wooting::wooting_usb_find_keyboard();
+/// The info cached by the last refresh, indexed the same way as the SDK device list
+pub fn device_info() -> Vec<DeviceInfo> {
+    DEVICE_INFO.read().unwrap().clone()
+}
 
-        let len = u8::MAX as usize + 3;
-        let mut buff = vec![0u8; len];
-        wooting::wooting_usb_send_feature_with_response(buff.as_mut_ptr(), len, 1, 0, 0, 0, 0);
+/// Reads the info of every connected device in a single SDK session and caches it, this reconnects to the devices so
+/// it should only be called on startup or an explicit refresh
+pub fn refresh_device_info() -> Vec<DeviceInfo> {
+    read_device_info(&[])
+}
 
-        let major = buff[5];
-        let minor = buff[6];
-        let patch = buff[7];
+/// Reconnects to the devices after one was plugged in or removed, only devices that were not connected before are logged
+pub fn update_device_info() -> Vec<DeviceInfo> {
+    read_device_info(&device_info())
+}
 
-        let firmware_version = format!("{}.{}.{}", major, minor, patch);
+/// Reads every connected device, devices with the same serial as one in `known` are not logged again
+fn read_device_info(known: &[DeviceInfo]) -> Vec<DeviceInfo> {
+    let info: Vec<DeviceInfo> = {
+        let _sdk = SDK_LOCK.lock().unwrap();
+        unsafe {
+            wooting::wooting_usb_disconnect(false);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            wooting::wooting_usb_find_keyboard();
 
-        /*
-        if let Ok(result) = compare_versions(&firmware_version, "2.8.0") {
-            if result == std::cmp::Ordering::Greater {
-                return (firmware_version, true);
-            }
+            (0..wooting::wooting_usb_device_count())
+                .filter(|index| wooting::wooting_usb_select_device(*index))
+                .map(|_| read_selected_device())
+                .collect()
         }
+    };
+
+    for device in info.iter().filter(|device| !is_known(known, device)) {
+        log_device_info(device);
+    }
+
+    *DEVICE_INFO.write().unwrap() = info.clone();
+    info
+}
+
+/// Whether `device` is one of `devices`, devices without a serial can not be followed and are never known
+pub fn is_known(devices: &[DeviceInfo], device: &DeviceInfo) -> bool {
+    device.serial.is_some() && devices.iter().any(|other| other.serial == device.serial)
+}
+
+fn log_device_info(device: &DeviceInfo) {
+    logf!(
+        Info,
+        "Device info for {}: Serial: {}, Firmware: {} ({}), Type: {}, Lighting: {}x{}",
+        device.model,
+        device.serial.as_deref().unwrap_or("N/A"),
+        device
+            .firmware
+            .map(|firmware| firmware.to_string())
+            .unwrap_or("N/A".to_string()),
+        device
+            .compatibility
+            .as_ref()
+            .map(|compatibility| compatibility.description())
+            .unwrap_or("Unknown"),
+        device.meta.description(),
+        device.rgb_size.0,
+        device.rgb_size.1
+    );
+
+    let Some(compatibility) = device.compatibility.as_ref() else {
+        return;
+    };
+    if compatibility.status == FirmwareStatus::KnownBroken {
+        logf!(
+            Warning,
+            "Firmware {} of {} is known to be broken: {}",
+            device
+                .firmware
+                .map(|firmware| firmware.to_string())
+                .unwrap_or_default(),
+            device.model,
+            compatibility.note.as_deref().unwrap_or("no details")
+        );
+    }
+    if !compatibility.supports(FirmwareFeature::Lighting) {
+        logf!(
+            Warning,
+            "Lighting is disabled for {} because its firmware does not support it",
+            device.model
+        );
+    }
+}
+
+/// Reads the device currently selected in the SDK, the caller has to hold the SDK lock
+unsafe fn read_selected_device() -> DeviceInfo {
+    let wooting_usb_meta = *wooting::wooting_usb_get_meta();
+    let model = CStr::from_ptr(wooting_usb_meta.model)
+        .to_str()
+        .log_expect(LogImportance::Error, "Failed to convert device name to str")
+        .to_string();
+    let meta = device_meta(&wooting_usb_meta);
+
+    let firmware = feature_report(FEATURE_GET_VERSION).map(|buff| FirmwareVersion {
+        major: buff[5],
+        minor: buff[6],
+        patch: buff[7],
+    });
+
+    let compatibility = firmware.map(|firmware| firmware::check_compatibility(&model, firmware));
+    // Features of firmware that could not be read are assumed to be there
+    let supports = |feature| {
+        compatibility
+            .as_ref()
+            .is_none_or(|compatibility: &FirmwareCompatibility| compatibility.supports(feature))
+    };
 
-        (firmware_version, false)
-        */
+    let serial = match supports(FirmwareFeature::SerialReport) {
+        true => feature_report(FEATURE_GET_SERIAL),
+        false => None,
+    };
+    let creation = serial.as_ref().map(|buff| (buff[8], 2000 + buff[7] as u16));
+    let serial = serial.map(|buff| {
+        format!(
+            "A{:02}B{:02}{:02}W{:02}{}H{:05}",
+            u16::from_le_bytes([buff[5], buff[6]]),
+            buff[7],
+            buff[8],
+            u16::from_le_bytes([buff[9], buff[10]]),
+            u16::from_le_bytes([buff[11], buff[12]]),
+            u32::from_le_bytes([buff[13], buff[14], buff[15], buff[16]])
+        )
+    });
 
-        firmware_version
+    // Output is paused the same way as for a device without lighting
+    let rgb_size = match supports(FirmwareFeature::Lighting) {
+        true => resolve_rgb_size(&model, meta).unwrap_or((0, 0)),
+        false => (0, 0),
+    };
+
+    DeviceInfo {
+        model,
+        serial,
+        creation,
+        firmware,
+        compatibility,
+        meta,
+        rgb_size,
     }
 }
 
+/// Sends a feature report to the selected device and returns the raw response, the payload starts at byte 5.
+/// The caller has to hold the SDK lock.
+unsafe fn feature_report(command: u8) -> Option<Vec<u8>> {
+    let len = u8::MAX as usize + 3;
+
+    for attempt in 0..FEATURE_ATTEMPTS {
+        let mut buff = vec![0u8; len];
+        let read = wooting::wooting_usb_send_feature_with_response(
+            buff.as_mut_ptr(),
+            len,
+            command,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if read > 0 && buff[5..].iter().any(|byte| *byte != 0) {
+            return Some(buff);
+        }
+
+        logf!(
+            Warning,
+            "Failed to get feature report {} retry {}",
+            command,
+            attempt
+        );
+    }
+
+    None
+}
+
+/// Runs every key with a led through the `pipeline` and sends the result to the device, returns the frame it sent
 pub fn draw_rgb(
-    resized_capture: image::DynamicImage,
-    brightness: u8,
-    red_shift_fix: bool,
-    highlight_wasd: bool,
+    device: &mut dyn RgbDevice,
+    resized_capture: &image::DynamicImage,
+    pipeline: &mut ColorPipeline,
     model_name: String,
-) {
-    unsafe {
-        for (x, y, pixel) in resized_capture.pixels() {
-            let image::Rgba([r, g, b, _]) = pixel;
-
-            // When highlight WASD is enabled, the WASD keys are set to red
-            if !(!highlight_wasd
-                || x != 2 && y != 2
-                || y != 1 && y != 2
-                || x != 2 && x != 1 && x != 3)
-            {
-                wooting::wooting_rgb_array_set_single(y as u8 + 1, x as u8, 255, 0, 0);
-                continue;
-            }
+) -> KeyFrame {
+    let definition = definitions::find_definition(&model_name);
+    let first_row = device.first_row();
 
-            // On 60HE models, the spacebar area is skipped for redshift fix due to the rgb lights not being covered by the keyswitches
-            if model_name == "Wooting 60HE"
-                || model_name == "Wooting 60HE (ARM)" && y == 4 && x > 3 && x < 10
-            {
-                wooting::wooting_rgb_array_set_single(
-                    y as u8 + 1,
-                    x as u8,
-                    (r as f32 * (brightness as f32 * 0.01)).round() as u8,
-                    (g as f32 * (brightness as f32 * 0.01)).round() as u8,
-                    (b as f32 * (brightness as f32 * 0.01)).round() as u8,
-                );
-
-                continue;
-            }
+    let mut keys: Vec<Key> = resized_capture
+        .pixels()
+        .map(|(x, y, image::Rgba([r, g, b, _]))| Key {
+            row: y as u8 + first_row,
+            column: x as u8,
+            color: [r as f32, g as f32, b as f32],
+            exposed: definition
+                .as_ref()
+                .is_some_and(|definition| definition.is_exposed(y as u8 + first_row, x as u8)),
+        })
+        .filter(|key| {
+            definition
+                .as_ref()
+                .is_none_or(|definition| definition.has_led(key.row, key.column))
+        })
+        .collect();
 
-            let (adjusted_r, adjusted_b) = match (red_shift_fix, r, b) {
-                (true, r, b) => (r as f32 * 0.55, b as f32 * 1.2),
-                (false, r, b) => (r as f32, b as f32),
-            };
+    pipeline.run(&mut keys);
 
-            let red = (adjusted_r * (brightness as f32 * 0.01)).round() as u8;
-            let green = (g as f32 * (brightness as f32 * 0.01)).round() as u8;
-            let blue = (adjusted_b * (brightness as f32 * 0.01)).round() as u8;
+    let mut frame = [[(0, 0, 0); MATRIX_COLUMNS]; MATRIX_ROWS];
+    for key in keys {
+        let [red, green, blue] = key
+            .color
+            .map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+        device.set_key(key.row, key.column, (red, green, blue));
 
-            wooting::wooting_rgb_array_set_single(y as u8 + 1, x as u8, red, green, blue);
+        if let Some(color) = frame
+            .get_mut(key.row as usize)
+            .and_then(|row| row.get_mut(key.column as usize))
+        {
+            *color = (red, green, blue);
         }
-
-        wooting::wooting_rgb_array_update_keyboard();
     }
+
+    device.flush();
+    frame
 }
 
 pub fn reconnect_device() {
@@ -164,6 +568,7 @@ pub fn reconnect_device() {
 
 pub fn exit_rgb() {
     logf!(Info, "Exiting RGB Device");
+    let _sdk = SDK_LOCK.lock().unwrap();
     unsafe {
         wooting::wooting_rgb_reset();
         wooting::wooting_rgb_reset_rgb();
@@ -172,6 +577,7 @@ pub fn exit_rgb() {
 }
 
 pub fn update_rgb() {
+    let _sdk = SDK_LOCK.lock().unwrap();
     unsafe {
         wooting::wooting_rgb_array_update_keyboard();
     }